@@ -20,6 +20,7 @@ fn main() {
     println!("cargo::rustc-check-cfg=cfg(debugger_helper)");
     println!("cargo::rustc-check-cfg=cfg(remote_env)");
     println!("cargo::rustc-check-cfg=cfg(unix_kvm)");
+    println!("cargo::rustc-check-cfg=cfg(procfs)");
 
     let unix = env::var("CARGO_CFG_UNIX");
     let os = env::var("CARGO_CFG_TARGET_OS");
@@ -29,6 +30,7 @@ fn main() {
         (Ok(_), Ok("linux")) => {
             println!("cargo:rustc-cfg=debugger_helper");
             println!("cargo:rustc-cfg=remote_env");
+            println!("cargo:rustc-cfg=procfs");
         }
         (Ok(_), Ok("macos")) => {
             println!("cargo:rerun-if-changed=src/apple-sysctl-wrapper.h");
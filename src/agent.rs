@@ -0,0 +1,454 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::definition::{AppError, AppResult};
+use crate::env::Env;
+use crate::snapshot::Snapshot;
+
+/// Requests and responses are single lines of JSON, so anything longer is not a request
+const MAX_REQUEST: u64 = 64 * 1024;
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where an agent listens
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    Unix(PathBuf),
+    Tcp(String),
+}
+
+impl FromStr for Endpoint {
+    type Err = String;
+
+    /// `unix:///path/to.sock` or `tcp://host:port`
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = spec.strip_prefix("unix://") {
+            return Ok(Self::Unix(PathBuf::from(path)));
+        }
+        if let Some(address) = spec.strip_prefix("tcp://") {
+            return Ok(Self::Tcp(address.to_owned()));
+        }
+        Err(format!(
+            "invalid endpoint: {spec}, expected unix:///path or tcp://host:port"
+        ))
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unix(path) => write!(f, "unix://{}", path.display()),
+            Self::Tcp(address) => write!(f, "tcp://{address}"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Request {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token: Option<String>,
+    /// `self`, `pid:<PID>` or `name:<NAME>`
+    target: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Response {
+    Snapshot { snapshot: Snapshot },
+    Error { error: String },
+}
+
+/// Ask an agent for the environment of `target`
+pub fn query(endpoint: &Endpoint, token: Option<&str>, target: &str) -> AppResult<Env> {
+    let request = Request {
+        token: token.map(str::to_owned),
+        target: target.to_owned(),
+    };
+    let response = match endpoint {
+        Endpoint::Unix(path) => {
+            let stream = UnixStream::connect(path)?;
+            stream.set_read_timeout(Some(TIMEOUT))?;
+            exchange(stream, &request)?
+        }
+        Endpoint::Tcp(address) => {
+            let stream = TcpStream::connect(address)?;
+            stream.set_read_timeout(Some(TIMEOUT))?;
+            exchange(stream, &request)?
+        }
+    };
+    match response {
+        Response::Snapshot { snapshot } => snapshot.env(),
+        Response::Error { error } => Err(AppError::Remote(error)),
+    }
+}
+
+fn exchange<S: Read + Write>(mut stream: S, request: &Request) -> AppResult<Response> {
+    let mut line = serde_json::to_vec(request)?;
+    line.push(b'\n');
+    stream.write_all(&line)?;
+    stream.flush()?;
+
+    let mut response = Vec::new();
+    BufReader::new(stream).read_until(b'\n', &mut response)?;
+    Ok(serde_json::from_slice(&response)?)
+}
+
+#[cfg(procfs)]
+pub use server::serve;
+
+#[cfg(procfs)]
+mod server {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::sync::mpsc::SyncSender;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use super::{Endpoint, MAX_REQUEST, Request, Response, TIMEOUT};
+    use crate::args::ServeArgs;
+    use crate::definition::{AppError, AppResult};
+    use crate::env::{Env, RecordPair};
+    use crate::pattern::Pattern;
+    use crate::procfs::Procfs;
+    use crate::selector::Selector;
+    use crate::snapshot::Snapshot;
+    use crate::source::{ReadOptions, Source};
+
+    /// Connections answered at the same time. Further ones wait in the queue, and then in the listen backlog
+    const WORKERS: usize = 8;
+
+    /// A socket of any endpoint
+    trait Stream: Read + Write + Send {
+        fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()>;
+    }
+
+    impl Stream for UnixStream {
+        fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+            Self::set_read_timeout(self, timeout)
+        }
+    }
+
+    impl Stream for TcpStream {
+        fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+            Self::set_read_timeout(self, timeout)
+        }
+    }
+
+    /// An accepted connection, which must send its whole request before `deadline`,
+    /// so that a client sending a byte now and then cannot hold a worker
+    struct Connection {
+        stream: Box<dyn Stream>,
+        deadline: Instant,
+    }
+
+    impl Connection {
+        fn new<S: Stream + 'static>(stream: S, timeout: Duration) -> Self {
+            Self {
+                stream: Box::new(stream),
+                deadline: Instant::now() + timeout,
+            }
+        }
+    }
+
+    impl Read for Connection {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let remaining = self.deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(std::io::ErrorKind::TimedOut.into());
+            }
+            self.stream.set_read_timeout(Some(remaining))?;
+            self.stream.read(buf)
+        }
+    }
+
+    impl Write for Connection {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.stream.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.stream.flush()
+        }
+    }
+
+    /// What clients may ask for
+    pub struct Policy {
+        token: Option<String>,
+        names: Vec<Pattern>,
+        variables: Vec<Pattern>,
+        /// How the processes clients ask for are read
+        options: ReadOptions,
+    }
+
+    impl Policy {
+        fn new(args: &ServeArgs, regex: bool, options: &ReadOptions) -> AppResult<Self> {
+            let patterns = |patterns: &[String]| -> AppResult<Vec<Pattern>> {
+                patterns
+                    .iter()
+                    .map(|pattern| Pattern::new(pattern, regex))
+                    .collect()
+            };
+            let token = match &args.token_file {
+                Some(path) => Some(std::fs::read_to_string(path)?.trim().to_owned()),
+                None => None,
+            };
+            Ok(Self {
+                token,
+                names: patterns(&args.allow_name)?,
+                variables: patterns(&args.allow_variable)?,
+                options: options.clone(),
+            })
+        }
+
+        fn check_token(&self, token: Option<&str>) -> bool {
+            // Compare every byte, so the time taken does not tell how much of the token was right
+            self.token.as_ref().is_none_or(|expected| {
+                token.is_some_and(|token| {
+                    token.len() == expected.len()
+                        && token
+                            .bytes()
+                            .zip(expected.bytes())
+                            .fold(0, |diff, (a, b)| diff | (a ^ b))
+                            == 0
+                })
+            })
+        }
+
+        fn allows_process(&self, procfs: &Procfs, pid: u32) -> bool {
+            let comm = procfs.stat(pid).map(|stat| stat.comm).unwrap_or_default();
+            let exe = procfs.exe(pid).ok();
+            let exe = exe
+                .as_ref()
+                .and_then(|exe| exe.file_name())
+                .map(std::os::unix::ffi::OsStrExt::as_bytes);
+            self.names.iter().any(|pattern| {
+                pattern.matches(comm.as_bytes()) || exe.is_some_and(|exe| pattern.matches(exe))
+            })
+        }
+
+        fn filter(&self, env: &mut Env) {
+            if !self.variables.is_empty() {
+                env.retain(|RecordPair(key, _)| {
+                    self.variables.iter().any(|pattern| pattern.matches(key))
+                });
+            }
+        }
+
+        fn answer(&self, request: &Request) -> AppResult<Snapshot> {
+            let procfs = &self.options.procfs;
+            if !self.check_token(request.token.as_deref()) {
+                return Err(AppError::Remote("invalid token".to_owned()));
+            }
+
+            // Identified before the policy is checked, so that a process which takes over the PID
+            // afterwards is not read in place of the allowed one
+            let process = if request.target == "self" {
+                procfs.stat(procfs.self_pid())?.process_id()
+            } else if let Some(pid) = request.target.strip_prefix("pid:") {
+                let pid = pid
+                    .parse()
+                    .map_err(|_| AppError::InvalidPid(pid.to_owned()))?;
+                procfs.stat(pid)?.process_id()
+            } else if let Some(name) = request.target.strip_prefix("name:") {
+                match Selector::Name(name.to_owned()).resolve(procfs)?[..] {
+                    [process] => process,
+                    _ => {
+                        return Err(AppError::Remote(format!(
+                            "several processes are named {name}"
+                        )));
+                    }
+                }
+            } else {
+                return Err(AppError::Remote(format!(
+                    "invalid target: {}",
+                    request.target
+                )));
+            };
+
+            if !self.allows_process(procfs, process.pid) {
+                return Err(AppError::Remote(format!(
+                    "process {} is not allowed",
+                    process.pid
+                )));
+            }
+            let source = Source::Process(process);
+            let mut env = source.read(&self.options)?;
+            self.filter(&mut env);
+            Ok(Snapshot::new(&source, &self.options, &env, &[]))
+        }
+
+        /// Answer a single request on a connection
+        fn handle<S: Read + Write>(&self, mut stream: S) -> AppResult<()> {
+            let mut line = Vec::new();
+            BufReader::new((&mut stream).take(MAX_REQUEST)).read_until(b'\n', &mut line)?;
+            let response = serde_json::from_slice(&line)
+                .map_err(AppError::from)
+                .and_then(|request: Request| self.answer(&request))
+                .map_or_else(
+                    |err| Response::Error {
+                        error: error_message(err),
+                    },
+                    |snapshot| Response::Snapshot { snapshot },
+                );
+
+            let mut line = serde_json::to_vec(&response)?;
+            line.push(b'\n');
+            stream.write_all(&line)?;
+            Ok(())
+        }
+    }
+
+    fn error_message(err: AppError) -> String {
+        match err {
+            AppError::Remote(message) => message,
+            err => err.to_string(),
+        }
+    }
+
+    /// Start the workers, and return where to queue connections for them
+    fn spawn_workers(policy: &Arc<Policy>) -> SyncSender<Connection> {
+        let (sender, receiver) = std::sync::mpsc::sync_channel::<Connection>(WORKERS);
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..WORKERS {
+            let policy = Arc::clone(policy);
+            let receiver = Arc::clone(&receiver);
+            std::thread::spawn(move || {
+                // The lock is only held while waiting for the next connection
+                while let Some(connection) = receiver
+                    .lock()
+                    .ok()
+                    .and_then(|receiver| receiver.recv().ok())
+                {
+                    if let Err(err) = policy.handle(connection) {
+                        eprintln!("Failed to answer a request: {err}");
+                    }
+                }
+            });
+        }
+        sender
+    }
+
+    /// Answer requests on every endpoint until killed
+    pub fn serve(args: &ServeArgs, regex: bool, options: &ReadOptions) -> AppResult<()> {
+        let policy = Arc::new(Policy::new(args, regex, options)?);
+        let queue = spawn_workers(&policy);
+        let mut listeners = Vec::new();
+        for endpoint in &args.listen {
+            let queue = queue.clone();
+            let listener = match endpoint {
+                Endpoint::Unix(path) => {
+                    // A socket left over by a previous run would make bind fail
+                    if std::fs::symlink_metadata(path)
+                        .is_ok_and(|metadata| metadata.file_type().is_socket())
+                    {
+                        std::fs::remove_file(path)?;
+                    }
+                    let listener = UnixListener::bind(path)?;
+                    std::thread::spawn(move || {
+                        for stream in listener.incoming().filter_map(Result::ok) {
+                            let _ = stream.set_write_timeout(Some(TIMEOUT));
+                            let _ = queue.send(Connection::new(stream, TIMEOUT));
+                        }
+                    })
+                }
+                Endpoint::Tcp(address) => {
+                    let listener = TcpListener::bind(address)?;
+                    std::thread::spawn(move || {
+                        for stream in listener.incoming().filter_map(Result::ok) {
+                            let _ = stream.set_write_timeout(Some(TIMEOUT));
+                            let _ = queue.send(Connection::new(stream, TIMEOUT));
+                        }
+                    })
+                }
+            };
+            eprintln!("Listening on {endpoint}");
+            listeners.push(listener);
+        }
+        for listener in listeners {
+            let _ = listener.join();
+        }
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::{Connection, Policy};
+        use crate::agent::{Request, Response, exchange};
+        use crate::pattern::Pattern;
+        use crate::source::ReadOptions;
+        use std::io::Write;
+        use std::os::unix::net::UnixStream;
+        use std::time::{Duration, Instant};
+
+        fn policy(token: Option<&str>, name: &str) -> Policy {
+            Policy {
+                token: token.map(str::to_owned),
+                names: vec![Pattern::new(name, false).unwrap()],
+                variables: vec![Pattern::new("PATH", false).unwrap()],
+                options: ReadOptions::default(),
+            }
+        }
+
+        fn ask(policy: &Policy, token: Option<&str>, target: &str) -> Response {
+            let (client, server) = UnixStream::pair().unwrap();
+            std::thread::scope(|scope| {
+                scope.spawn(|| policy.handle(server).unwrap());
+                let request = Request {
+                    token: token.map(str::to_owned),
+                    target: target.to_owned(),
+                };
+                exchange(client, &request).unwrap()
+            })
+        }
+
+        #[test]
+        fn answer_within_policy() {
+            let policy = policy(Some("secret"), "*");
+            let Response::Snapshot { snapshot } = ask(&policy, Some("secret"), "self") else {
+                panic!("expected a snapshot");
+            };
+            let env = snapshot.env().unwrap();
+            assert!(env.iter().all(|record| record.0 == b"PATH"));
+
+            assert!(matches!(
+                ask(&policy, Some("wrong"), "self"),
+                Response::Error { error } if error == "invalid token"
+            ));
+            assert!(matches!(ask(&policy, None, "self"), Response::Error { .. }));
+            assert!(matches!(
+                ask(&policy, Some("secret"), "nonsense"),
+                Response::Error { .. }
+            ));
+        }
+
+        #[test]
+        fn deny_other_names() {
+            let policy = policy(None, "sshd");
+            assert!(matches!(
+                ask(&policy, None, &format!("pid:{}", std::process::id())),
+                Response::Error { error } if error.ends_with("is not allowed")
+            ));
+        }
+
+        #[test]
+        fn slow_client_times_out() {
+            let policy = policy(None, "*");
+            let (mut client, server) = UnixStream::pair().unwrap();
+            client.write_all(b"{\"target\":").unwrap();
+            let start = Instant::now();
+            assert!(
+                policy
+                    .handle(Connection::new(server, Duration::from_millis(100)))
+                    .is_err()
+            );
+            assert!(start.elapsed() < Duration::from_secs(5));
+        }
+    }
+}
@@ -1,5 +1,13 @@
-use clap::{CommandFactory, Parser, ValueEnum, error::ErrorKind};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum, error::ErrorKind};
+use std::ffi::OsString;
 use std::path::PathBuf;
+use std::time::Duration;
+
+#[cfg(target_family = "unix")]
+use crate::agent::Endpoint;
+#[cfg(procfs)]
+use crate::selector::Selector;
+use crate::source::Source;
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
 pub enum ColorMode {
@@ -27,18 +35,361 @@ pub enum DebuggerHelper {
     Gdb,
 }
 
+#[cfg(procfs)]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
+pub enum PidNamespace {
+    /// PIDs as seen in the procfs root
+    Host,
+    /// PIDs as seen inside the innermost PID namespace of the process, for example a container
+    Container,
+}
+
+#[derive(Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
+pub enum LoadFormat {
+    /// NUL-separated records, as printed with --null
+    #[default]
+    Null,
+    /// KEY=VALUE lines with quoting and comments
+    Dotenv,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
+pub enum DiffFormat {
+    Report,
+    Unified,
+    Json,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
+pub enum CaptureFormat {
+    Report,
+    Unified,
+    Json,
+    /// POSIX shell commands which apply the changes
+    Sh,
+    /// A dotenv file with the variables which were set
+    Dotenv,
+}
+
+#[cfg(target_family = "unix")]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Compare environment variables of two sources. Exits with 1 if they differ
+    Diff(DiffArgs),
+    /// Answer requests for environments of processes on a socket, within an allow-list
+    #[cfg(procfs)]
+    Serve(ServeArgs),
+    /// Explain what blocks reading the environment of a process, and which ways of reading environments work on this host
+    #[cfg(procfs)]
+    Doctor(DoctorArgs),
+    /// Run a command with the environment of the source, modified like env does. Without a command, print the result
+    Exec(ExecArgs),
+    /// Substitute environment variables of the source into a template, like envsubst
+    Subst(SubstArgs),
+    /// Source a script in a shell, and report the environment variables it changed
+    #[cfg(target_family = "unix")]
+    Capture(CaptureArgs),
+    /// Print a prompt hook which loads the nearest .env or .envrc.env file, for example `eval "$(printenv2 hook bash)"`
+    #[cfg(target_family = "unix")]
+    Hook(ShellArgs),
+    /// Print shell code which reverts the previously loaded file, and loads the nearest allowed one. Run by the hook
+    #[cfg(target_family = "unix")]
+    Export(ShellArgs),
+    /// Trust a file to be loaded by the hook, as it is now
+    #[cfg(target_family = "unix")]
+    Allow(AllowArgs),
+    /// Show what commands changed in the environment of interactive shells, as recorded by the history hook
+    #[cfg(target_family = "unix")]
+    History(HistoryArgs),
+    /// Record what the last command changed in the environment of the shell. Run by the history hook
+    #[cfg(target_family = "unix")]
+    Record(RecordArgs),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct DiffArgs {
+    /// Old side: "self" for the current process, "pid:<PID>", "core:<PATH>", "via:<PREFIX>" for the environment
+    /// a command prefix such as `ssh host` runs in, or a file in the format of --load-format
+    pub old: Source,
+
+    /// New side, in the same form as the old side
+    pub new: Source,
+
+    /// Output format
+    #[clap(long, value_enum, required = false)]
+    #[arg(default_value_t = DiffFormat::Report)]
+    pub format: DiffFormat,
+
+    /// Ignore variables whose names match a glob, or a regular expression with --regex. Can be repeated
+    #[clap(long, value_name = "PATTERN", required = false)]
+    pub ignore: Vec<String>,
+
+    /// Compare names of variables case-insensitively
+    #[clap(long)]
+    pub ignore_case: bool,
+}
+
+#[cfg(target_family = "unix")]
+#[derive(clap::Args, Debug)]
+pub struct ShellArgs {
+    #[clap(value_enum)]
+    pub shell: Shell,
+}
+
+#[cfg(target_family = "unix")]
+#[derive(clap::Args, Debug)]
+pub struct AllowArgs {
+    /// File to trust [default: the one the hook would load]
+    #[clap(value_parser = clap::value_parser!(PathBuf))]
+    pub file: Option<PathBuf>,
+
+    /// Stop trusting the file
+    #[clap(long)]
+    pub revoke: bool,
+}
+
+#[cfg(target_family = "unix")]
+#[derive(clap::Args, Debug)]
+pub struct HistoryArgs {
+    /// Only changes to variables whose names match a glob, or a regular expression with --regex
+    pub variables: Vec<String>,
+
+    /// Only changes recorded in this session: `$_printenv2_session` of the shell, or its PID
+    #[clap(long, value_parser = crate::history::parse_session)]
+    pub session: Option<String>,
+
+    /// Print the hook recording changes, for example `eval "$(printenv2 history --hook bash)"`
+    #[clap(long, value_enum, value_name = "SHELL", conflicts_with_all = ["variables", "session"])]
+    pub hook: Option<Shell>,
+}
+
+#[cfg(target_family = "unix")]
+#[derive(clap::Args, Debug)]
+pub struct RecordArgs {
+    /// Session the shell records in
+    #[clap(long, value_parser = crate::history::parse_session)]
+    pub session: String,
+
+    /// The command which ran last
+    #[clap(long, default_value = "")]
+    pub command: String,
+}
+
+#[cfg(target_family = "unix")]
+#[derive(clap::Args, Debug)]
+pub struct CaptureArgs {
+    /// Shell to source the script in, unless it is given as `SHELL -c SCRIPT`
+    #[clap(long, default_value = "sh")]
+    pub shell: String,
+
+    /// Output format
+    #[clap(long, value_enum, required = false)]
+    #[arg(default_value_t = CaptureFormat::Report)]
+    pub format: CaptureFormat,
+
+    /// Ignore variables whose names match a glob, or a regular expression with --regex. Can be repeated
+    #[clap(long, value_name = "PATTERN", required = false)]
+    pub ignore: Vec<String>,
+
+    /// Command which sources the script, after --, such as `. ./setup.sh`, or a script given as `bash -c 'source venv/bin/activate'`
+    #[clap(last = true, value_name = "SCRIPT", required = true)]
+    pub script: Vec<String>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct SubstArgs {
+    /// Template to read [default: stdin]
+    #[clap(value_parser = clap::value_parser!(PathBuf))]
+    pub template: Option<PathBuf>,
+
+    /// Fail when a variable without a default is not set, instead of substituting an empty string
+    #[clap(long)]
+    pub strict: bool,
+
+    /// Only substitute variables whose names match a glob, or a regular expression with --regex, and leave other references as they are. Can be repeated
+    #[clap(long, value_name = "PATTERN", required = false)]
+    pub only: Vec<String>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ExecArgs {
+    /// Start from an empty environment
+    #[clap(short = 'i', long)]
+    pub ignore_environment: bool,
+
+    /// Remove a variable. Can be repeated
+    #[clap(short = 'u', long, value_name = "NAME", required = false)]
+    pub unset: Vec<String>,
+
+    /// Change the working directory before running the command
+    #[clap(short = 'C', long, value_name = "DIR", value_parser = clap::value_parser!(PathBuf), requires = "command", required = false)]
+    pub chdir: Option<PathBuf>,
+
+    /// Use NUL as delimiter when printing the environment
+    #[clap(short = '0', long)]
+    pub null: bool,
+
+    /// Variables to set
+    #[clap(value_name = "NAME=VALUE", value_parser = crate::exec::parse_assignment, required = false)]
+    pub assignments: Vec<(String, String)>,
+
+    /// Command to run and its arguments, after --
+    #[clap(last = true, value_name = "COMMAND", required = false)]
+    pub command: Vec<String>,
+}
+
+#[cfg(procfs)]
+#[derive(clap::Args, Debug)]
+pub struct DoctorArgs {
+    /// Process to check. Exits with 1 if something blocks reading it
+    pub pid: Option<u32>,
+}
+
+#[cfg(procfs)]
+#[derive(clap::Args, Debug)]
+pub struct ServeArgs {
+    /// Where to listen: `unix:///path/to.sock`, or `tcp://host:port` which requires --token-file. Can be repeated
+    #[clap(long, value_name = "ENDPOINT", required = true)]
+    pub listen: Vec<Endpoint>,
+
+    /// File holding a shared token which clients must present
+    #[clap(long, value_name = "FILE", value_parser = clap::value_parser!(PathBuf), required = false)]
+    pub token_file: Option<PathBuf>,
+
+    /// Only serve processes whose name matches a glob, or a regular expression with --regex. Can be repeated
+    #[clap(long, value_name = "PATTERN", required = true)]
+    pub allow_name: Vec<String>,
+
+    /// Only return variables whose names match a glob, or a regular expression with --regex. Can be repeated [default: all]
+    #[clap(long, value_name = "PATTERN", required = false)]
+    pub allow_variable: Vec<String>,
+}
+
 /// Print environment variables
 #[derive(Parser, Debug)]
+#[allow(clippy::struct_excessive_bools)]
 #[clap(author, version, about, long_about = None)]
 pub struct Args {
     /// Use NUL as delimiter instead of newline
     #[clap(short = '0', long)]
     pub null: bool,
 
-    /// Read environment variables of another running process
+    /// Read environment variables of another running process. Can be repeated together with --matrix
     #[cfg(remote_env)]
     #[clap(long, required = false)]
-    pub pid: Option<u32>,
+    pub pid: Vec<u32>,
+
+    /// Select processes by name, matching either comm or the executable file name
+    #[cfg(procfs)]
+    #[clap(long, required = false)]
+    pub name: Option<String>,
+
+    /// Select the process whose PID is stored in a pidfile
+    #[cfg(procfs)]
+    #[clap(long, value_parser = clap::value_parser!(PathBuf), required = false)]
+    pub pidfile: Option<PathBuf>,
+
+    /// Select the parent process, which is usually the invoking shell
+    #[cfg(procfs)]
+    #[clap(long)]
+    pub parent: bool,
+
+    /// Select the parent process of PID
+    #[cfg(procfs)]
+    #[clap(long, value_name = "PID", required = false)]
+    pub ppid_of: Option<u32>,
+
+    /// Select processes in a cgroup, for example system.slice/foo.service
+    #[cfg(procfs)]
+    #[clap(long, required = false)]
+    pub cgroup: Option<String>,
+
+    /// Select processes by PIDs read from stdin, separated by whitespace
+    #[cfg(procfs)]
+    #[clap(long)]
+    pub stdin_pids: bool,
+
+    /// Select the process listening on a TCP port
+    #[cfg(procfs)]
+    #[clap(long, required = false)]
+    pub port: Option<u16>,
+
+    /// Select the process that bound a Unix socket
+    #[cfg(procfs)]
+    #[clap(long, value_name = "PATH", value_parser = clap::value_parser!(PathBuf), required = false)]
+    pub unix_socket: Option<PathBuf>,
+
+    /// Select processes holding a file open
+    #[cfg(procfs)]
+    #[clap(long, value_name = "PATH", value_parser = clap::value_parser!(PathBuf), required = false)]
+    pub holding: Option<PathBuf>,
+
+    /// Print environment variables of all accessible processes, grouped by PID
+    #[cfg(procfs)]
+    #[clap(long)]
+    pub all: bool,
+
+    /// Print environment variables of a process and all of its descendants, grouped by PID
+    #[cfg(procfs)]
+    #[clap(long, value_name = "PID", required = false)]
+    pub tree: Option<u32>,
+
+    /// Search environments of all accessible processes for KEY or KEY=VALUE, both given as globs
+    #[cfg(procfs)]
+    #[clap(long, value_name = "KEY[=VALUE]", required = false)]
+    pub find: Option<String>,
+
+    /// Interpret patterns as regular expressions instead of globs
+    #[clap(long, global = true)]
+    pub regex: bool,
+
+    /// Show which ancestor of the process introduced, changed or removed each variable
+    #[cfg(procfs)]
+    #[clap(long)]
+    pub blame: bool,
+
+    /// Show the command line, executable, working directory, owner, cgroups, start time and `AT_SECURE` of the process before its environment
+    #[cfg(procfs)]
+    #[clap(long)]
+    pub context: bool,
+
+    /// Print every process matched by a selector, grouped by PID
+    #[cfg(procfs)]
+    #[clap(long)]
+    pub all_matches: bool,
+
+    /// Read environment variables through a command prefix such as 'ssh host' or 'docker exec ctr', of --pid or of the command itself
+    #[clap(long, value_name = "COMMAND", required = false)]
+    pub via: Option<String>,
+
+    /// Ask an agent started with `printenv2 serve`, at `unix:///path/to.sock` or `tcp://host:port`, for the environment of --pid, --name or the agent itself
+    #[cfg(target_family = "unix")]
+    #[clap(long, value_name = "ENDPOINT", required = false)]
+    pub remote: Option<Endpoint>,
+
+    /// File holding the shared token of the agent given with --remote
+    #[cfg(target_family = "unix")]
+    #[clap(long, value_name = "FILE", value_parser = clap::value_parser!(PathBuf), requires = "remote", required = false)]
+    pub token_file: Option<PathBuf>,
+
+    /// Inspect processes through another procfs mount, for example the one of the host mounted into a container
+    #[cfg(procfs)]
+    #[clap(long, value_name = "PATH", value_parser = clap::value_parser!(PathBuf), global = true, required = false)]
+    #[arg(default_value = "/proc")]
+    pub proc_root: PathBuf,
+
+    /// PID namespace of PIDs given on the command line: with --pid, --tree and --ppid-of, `pid:` sides of diff, and doctor
+    #[cfg(procfs)]
+    #[clap(long, value_enum, required = false)]
+    #[arg(default_value_t = PidNamespace::Host)]
+    pub pid_ns: PidNamespace,
 
     #[cfg(debugger_helper)]
     #[clap(long, value_enum, required = false)]
@@ -46,7 +397,7 @@ pub struct Args {
     pub debugger_helper: Option<DebuggerHelper>,
 
     /// Control colorfulness of output
-    #[clap(long, value_enum, required = false)]
+    #[clap(long, value_enum, global = true, required = false)]
     #[arg(default_value_t = ColorMode::Auto)]
     pub color: ColorMode,
 
@@ -55,45 +406,209 @@ pub struct Args {
     #[arg(default_value_t = KeyOrder::None)]
     pub key_order: KeyOrder,
 
-    /// Escape control characters, for example line breaks [default: yes unless --null or --json is used]
-    #[clap(long, value_enum, required = false)]
+    /// Escape control characters, for example line breaks [default: yes unless --null or --json is used, or the program is installed as printenv]
+    #[clap(long, value_enum, global = true, required = false)]
     pub escape: Option<EscapeMode>,
 
-    /// Load environment variables from a file. The file should be in the same format as the output format of --null
+    /// Load environment variables from a file, in the format given by --load-format. Can be repeated together with --matrix
     #[clap(long, value_parser = clap::value_parser!(PathBuf), required = false)]
-    pub load: Option<PathBuf>,
+    pub load: Vec<PathBuf>,
+
+    /// Format of files given with --load, and of files compared with diff
+    #[clap(long, value_enum, global = true, required = false)]
+    #[arg(default_value_t = LoadFormat::Null)]
+    pub load_format: LoadFormat,
+
+    /// Expand ${NAME} references in dotenv values, with records above them and then the current environment.
+    /// Referring to a variable which is not set and has no default, or to a record further down the file, is an error [default]
+    #[clap(long, global = true, overrides_with = "no_expand")]
+    pub expand: bool,
+
+    /// Take dotenv values literally
+    #[clap(long, global = true, overrides_with = "expand")]
+    pub no_expand: bool,
+
+    /// Read environment variables of a crashed process from its core file: the ones at the time of the crash when the mapped libraries are still around, otherwise the initial ones. Can be repeated together with --matrix
+    #[clap(long, value_name = "FILE", value_parser = clap::value_parser!(PathBuf), required = false)]
+    pub core: Vec<PathBuf>,
+
+    /// Compare several sources side by side, one row per variable and one column per source
+    #[clap(long)]
+    pub matrix: bool,
+
+    /// Only show variables that differ between sources in --matrix
+    #[clap(long, requires = "matrix")]
+    pub differences_only: bool,
 
     /// Output in JSON format
     #[clap(long)]
     pub json: bool,
 
-    /// Filter by environment variable names, also omit key names
+    /// Save environment variables with metadata about their source to a snapshot file
+    #[clap(long, value_name = "FILE", value_parser = clap::value_parser!(PathBuf), required = false)]
+    pub save_snapshot: Option<PathBuf>,
+
+    /// Compare environment variables against a snapshot file. Exits with 1 on drift
+    #[clap(long, value_name = "FILE", value_parser = clap::value_parser!(PathBuf), required = false)]
+    pub against_snapshot: Option<PathBuf>,
+
+    /// Keep reading environment variables and print changes as timestamped events, until the process exits
+    #[clap(long)]
+    pub watch: bool,
+
+    /// How often to read environment variables in --watch mode, for example 500ms, 2s or 1m
+    #[clap(long, value_parser = crate::watch::parse_interval, requires = "watch", required = false)]
+    #[arg(default_value = "2s")]
+    pub interval: Duration,
+
+    /// Leave out variables which differ between otherwise identical shells: PWD, OLDPWD, SHLVL and _
+    #[clap(long)]
+    pub exclude_volatile: bool,
+
+    /// Report VARIABLES which are not set on stderr, with similar names which are
+    #[clap(long, requires = "variables")]
+    pub report_missing: bool,
+
+    /// Present the content of the file named by each `KEY_FILE` variable as `KEY`, like Docker secrets are read
+    #[clap(long)]
+    pub resolve_file_refs: bool,
+
+    /// Directory which files read by --resolve-file-refs must be in. Can be repeated
+    #[clap(long, value_name = "DIR", value_parser = clap::value_parser!(PathBuf), requires = "resolve_file_refs")]
+    #[arg(default_value = "/run/secrets")]
+    pub file_ref_dir: Vec<PathBuf>,
+
+    /// Largest file read by --resolve-file-refs, in bytes
+    #[clap(long, value_name = "BYTES", requires = "resolve_file_refs")]
+    #[arg(default_value_t = 65536)]
+    pub file_ref_max_size: u64,
+
+    /// Keep the `KEY_FILE` variables next to the `KEY` variables resolved from them
+    #[clap(long, requires = "resolve_file_refs")]
+    pub keep_file_refs: bool,
+
+    /// Print values resolved by --resolve-file-refs as <redacted>
+    #[clap(long, requires = "resolve_file_refs")]
+    pub redact_file_refs: bool,
+
+    /// Filter by environment variable names, also omit key names. Values are printed in the order of the names, and the exit status is 1 if any of them is not set
     #[clap(required = false)]
     pub variables: Vec<String>,
+
+    #[clap(subcommand)]
+    pub command: Option<Command>,
 }
 
-pub fn parse() -> Args {
-    let args = Args::parse();
+impl Args {
+    /// Whether output is delimited by NUL, with --null or `exec -0`
+    pub const fn null_output(&self) -> bool {
+        self.null || matches!(&self.command, Some(Command::Exec(exec_args)) if exec_args.null)
+    }
+}
 
-    if args.color == ColorMode::Never || (args.null && args.color == ColorMode::Auto) {
-        colored::control::set_override(false);
+#[cfg(procfs)]
+impl Args {
+    pub fn selectors(&self) -> Vec<Selector> {
+        let mut selectors = Vec::new();
+        if let Some(name) = &self.name {
+            selectors.push(Selector::Name(name.clone()));
+        }
+        if let Some(path) = &self.pidfile {
+            selectors.push(Selector::Pidfile(path.clone()));
+        }
+        if self.parent {
+            selectors.push(Selector::Parent);
+        }
+        if let Some(pid) = self.ppid_of {
+            selectors.push(Selector::PpidOf(pid));
+        }
+        if let Some(cgroup) = &self.cgroup {
+            selectors.push(Selector::Cgroup(cgroup.clone()));
+        }
+        if self.stdin_pids {
+            selectors.push(Selector::Stdin);
+        }
+        if let Some(port) = self.port {
+            selectors.push(Selector::Port(port));
+        }
+        if let Some(path) = &self.unix_socket {
+            selectors.push(Selector::UnixSocket(path.clone()));
+        }
+        if let Some(path) = &self.holding {
+            selectors.push(Selector::Holding(path.clone()));
+        }
+        selectors
     }
+}
 
-    if args.null && (args.color == ColorMode::Always || args.escape == Some(EscapeMode::Yes)) {
-        let mut cmd = Args::command();
-        cmd.error(
-            ErrorKind::ArgumentConflict,
-            "Null mode cannot be used together with other rich-format switches",
-        )
-        .exit();
+/// Name the program was invoked as, such as `printenv` for a symlink
+pub fn program_name() -> Option<String> {
+    let argv0 = std::env::args_os().next()?;
+    let stem = std::path::Path::new(&argv0).file_stem()?;
+    Some(stem.to_string_lossy().into_owned())
+}
+
+/// Arguments of `printenv [OPTION]... [VARIABLE]...`, with `--` inserted before the first variable,
+/// so a variable named like a subcommand such as `diff` is printed
+pub fn printenv_argv(argv: impl IntoIterator<Item = OsString>) -> Vec<OsString> {
+    let command = Args::command();
+    let takes_value =
+        |arg: Option<&clap::Arg>| arg.is_some_and(|arg| arg.get_action().takes_values());
+    let mut argv = argv.into_iter();
+    let mut rewritten = vec![argv.next().unwrap_or_default()];
+    while let Some(arg) = argv.next() {
+        let word = arg.to_string_lossy().into_owned();
+        // Whether the next word is the value of this option
+        let value = if word == "--" {
+            rewritten.push(arg);
+            break;
+        } else if let Some(long) = word.strip_prefix("--") {
+            !long.contains('=')
+                && takes_value(
+                    command
+                        .get_arguments()
+                        .find(|arg| arg.get_long() == Some(long)),
+                )
+        } else if let Some(shorts) = word.strip_prefix('-').filter(|shorts| !shorts.is_empty()) {
+            // In a cluster such as `-0p`, the rest of the word after an option taking a value is the value
+            shorts
+                .char_indices()
+                .find(|(_, short)| {
+                    takes_value(
+                        command
+                            .get_arguments()
+                            .find(|arg| arg.get_short() == Some(*short)),
+                    )
+                })
+                .is_some_and(|(at, short)| at + short.len_utf8() == shorts.len())
+        } else {
+            rewritten.push(OsString::from("--"));
+            rewritten.push(arg);
+            break;
+        };
+        rewritten.push(arg);
+        if value {
+            rewritten.extend(argv.next());
+        }
     }
+    rewritten.extend(argv);
+    rewritten
+}
 
-    if !args.variables.is_empty() && args.key_order != KeyOrder::None {
-        let mut cmd = Args::command();
-        cmd.error(ErrorKind::ArgumentConflict, "VARIABLES cannot be sorted")
-            .exit();
+pub fn parse() -> Args {
+    // Installed as `env` or `printenv`, arguments are those of env or printenv, without subcommands
+    let args = match program_name().as_deref() {
+        Some("env") => Args::parse_from(crate::exec::env_argv(std::env::args_os())),
+        Some("printenv") => Args::parse_from(printenv_argv(std::env::args_os())),
+        _ => Args::parse(),
+    };
+
+    if args.color == ColorMode::Never || (args.null_output() && args.color == ColorMode::Auto) {
+        colored::control::set_override(false);
     }
 
+    check_output(&args);
+
     #[cfg(debugger_helper)]
     #[allow(clippy::collapsible_if)]
     if args.debugger_helper.is_some() {
@@ -113,16 +628,55 @@ pub fn parse() -> Args {
     }
 
     #[cfg(remote_env)]
-    if args.pid.is_some() && args.load.is_some() {
+    let sources = args.pid.len() + args.load.len() + args.core.len();
+    #[cfg(not(remote_env))]
+    let sources = args.load.len() + args.core.len();
+    if sources > 1 && !args.matrix {
         let mut cmd = Args::command();
         cmd.error(
             ErrorKind::ArgumentConflict,
-            "--pid and --load cannot be used together",
+            "Several --pid, --load or --core sources can only be used together with --matrix",
         )
         .exit();
     }
 
-    if args.null && args.load.is_some() {
+    if args.matrix && args.null {
+        let mut cmd = Args::command();
+        cmd.error(
+            ErrorKind::ArgumentConflict,
+            "--null and --matrix cannot be used together",
+        )
+        .exit();
+    }
+
+    #[cfg(procfs)]
+    check_process_selection(&args);
+
+    check_snapshot(&args);
+
+    check_via(&args);
+    #[cfg(target_family = "unix")]
+    check_remote(&args);
+    check_watch(&args, sources);
+
+    if matches!(args.command, Some(Command::Diff(_)))
+        && (sources > 0 || args.matrix || args.null || !args.variables.is_empty())
+    {
+        let mut cmd = Args::command();
+        cmd.error(
+            ErrorKind::ArgumentConflict,
+            "diff takes both of its sources as arguments, and cannot be used together with other modes",
+        )
+        .exit();
+    }
+
+    #[cfg(procfs)]
+    check_serve(&args, sources);
+    check_exec(&args);
+    #[cfg(target_family = "unix")]
+    check_standalone(&args, sources);
+
+    if args.null && !args.load.is_empty() {
         let mut cmd = Args::command();
         cmd.error(
             ErrorKind::ArgumentConflict,
@@ -153,3 +707,402 @@ pub fn parse() -> Args {
 
     args
 }
+
+#[cfg(procfs)]
+fn check_process_selection(args: &Args) {
+    let selectors =
+        usize::from(!args.pid.is_empty() || !args.load.is_empty() || !args.core.is_empty())
+            + usize::from(args.all)
+            + usize::from(args.tree.is_some())
+            + usize::from(args.find.is_some())
+            + args.selectors().len();
+    if selectors > 1 {
+        let mut cmd = Args::command();
+        cmd.error(
+            ErrorKind::ArgumentConflict,
+            "Only one way of selecting processes can be used: --pid, --load or --core, --all, --tree, --find or a process selector",
+        )
+        .exit();
+    }
+
+    if args.watch && (args.all || args.tree.is_some() || args.find.is_some() || args.all_matches) {
+        let mut cmd = Args::command();
+        cmd.error(
+            ErrorKind::ArgumentConflict,
+            "--watch only works with a single process",
+        )
+        .exit();
+    }
+
+    if args.blame
+        && (!args.load.is_empty()
+            || !args.core.is_empty()
+            || args.watch
+            || args.matrix
+            || args.all
+            || args.tree.is_some()
+            || args.find.is_some()
+            || args.all_matches
+            || args.null)
+    {
+        let mut cmd = Args::command();
+        cmd.error(
+            ErrorKind::ArgumentConflict,
+            "--blame only works with a single process",
+        )
+        .exit();
+    }
+
+    if args.find.is_some() && !args.variables.is_empty() {
+        let mut cmd = Args::command();
+        cmd.error(
+            ErrorKind::ArgumentConflict,
+            "--find cannot be used together with VARIABLES",
+        )
+        .exit();
+    }
+
+    if args.matrix && (args.all || args.tree.is_some() || args.find.is_some()) {
+        let mut cmd = Args::command();
+        cmd.error(
+            ErrorKind::ArgumentConflict,
+            "--matrix cannot be used together with --all, --tree or --find",
+        )
+        .exit();
+    }
+
+    if args.all_matches && args.selectors().is_empty() {
+        let mut cmd = Args::command();
+        cmd.error(
+            ErrorKind::MissingRequiredArgument,
+            "--all-matches requires a process selector",
+        )
+        .exit();
+    }
+
+    if args.null && (args.all_matches || args.all || args.tree.is_some() || args.find.is_some()) {
+        let mut cmd = Args::command();
+        cmd.error(
+            ErrorKind::ArgumentConflict,
+            "Null mode cannot be used together with grouped output",
+        )
+        .exit();
+    }
+
+    if args.resolve_file_refs
+        && (args.all_matches || args.all || args.tree.is_some() || args.find.is_some())
+    {
+        let mut cmd = Args::command();
+        cmd.error(
+            ErrorKind::ArgumentConflict,
+            "--resolve-file-refs cannot be used together with grouped output",
+        )
+        .exit();
+    }
+
+    check_context(args);
+}
+
+#[cfg(procfs)]
+fn check_context(args: &Args) {
+    if !args.context {
+        return;
+    }
+
+    if args.pid.is_empty() && args.selectors().is_empty() {
+        let mut cmd = Args::command();
+        cmd.error(
+            ErrorKind::MissingRequiredArgument,
+            "--context needs a process given by --pid or a selector",
+        )
+        .exit();
+    }
+
+    if args.null
+        || args.matrix
+        || args.watch
+        || args.blame
+        || args.all_matches
+        || args.via.is_some()
+        || args.remote.is_some()
+        || args.save_snapshot.is_some()
+        || args.against_snapshot.is_some()
+        || args.command.is_some()
+    {
+        let mut cmd = Args::command();
+        cmd.error(
+            ErrorKind::ArgumentConflict,
+            "--context only works when printing the environment of a single local process",
+        )
+        .exit();
+    }
+}
+
+fn check_snapshot(args: &Args) {
+    let snapshot = args.save_snapshot.is_some() || args.against_snapshot.is_some();
+    if args.save_snapshot.is_some() && args.against_snapshot.is_some() {
+        let mut cmd = Args::command();
+        cmd.error(
+            ErrorKind::ArgumentConflict,
+            "--save-snapshot and --against-snapshot cannot be used together",
+        )
+        .exit();
+    }
+
+    if snapshot
+        && (args.null || args.matrix || !args.variables.is_empty() || args.command.is_some())
+    {
+        let mut cmd = Args::command();
+        cmd.error(
+            ErrorKind::ArgumentConflict,
+            "Snapshots cannot be used together with --null, --matrix, VARIABLES or subcommands",
+        )
+        .exit();
+    }
+
+    if args.exclude_volatile && args.save_snapshot.is_none() {
+        let mut cmd = Args::command();
+        cmd.error(
+            ErrorKind::MissingRequiredArgument,
+            "--exclude-volatile requires --save-snapshot",
+        )
+        .exit();
+    }
+}
+
+fn check_via(args: &Args) {
+    if args.via.is_none() {
+        return;
+    }
+
+    #[cfg(procfs)]
+    let local = args.all
+        || args.tree.is_some()
+        || args.find.is_some()
+        || args.blame
+        || !args.selectors().is_empty()
+        || args.pid_ns == PidNamespace::Container
+        || args.proc_root != std::path::Path::new("/proc");
+    #[cfg(not(procfs))]
+    let local = false;
+    #[cfg(debugger_helper)]
+    let local = local || args.debugger_helper.is_some();
+
+    if local || !args.load.is_empty() || !args.core.is_empty() || args.command.is_some() {
+        let mut cmd = Args::command();
+        cmd.error(
+            ErrorKind::ArgumentConflict,
+            "--via cannot be used together with --load, --core, subcommands or inspecting local processes",
+        )
+        .exit();
+    }
+}
+
+#[cfg(procfs)]
+fn check_serve(args: &Args, sources: usize) {
+    if !matches!(args.command, Some(Command::Serve(_) | Command::Doctor(_))) {
+        return;
+    }
+
+    if sources > 0
+        || args.matrix
+        || args.null
+        || args.resolve_file_refs
+        || !args.variables.is_empty()
+    {
+        let mut cmd = Args::command();
+        cmd.error(
+            ErrorKind::ArgumentConflict,
+            "serve and doctor cannot be used together with other modes",
+        )
+        .exit();
+    }
+
+    let Some(Command::Serve(serve_args)) = &args.command else {
+        return;
+    };
+    if args.json {
+        let mut cmd = Args::command();
+        cmd.error(
+            ErrorKind::ArgumentConflict,
+            "serve always answers in JSON, and does not take --json",
+        )
+        .exit();
+    }
+
+    let tcp = serve_args
+        .listen
+        .iter()
+        .any(|endpoint| matches!(endpoint, Endpoint::Tcp(_)));
+    if tcp && serve_args.token_file.is_none() {
+        let mut cmd = Args::command();
+        cmd.error(
+            ErrorKind::MissingRequiredArgument,
+            "Listening on TCP requires --token-file",
+        )
+        .exit();
+    }
+}
+
+fn check_output(args: &Args) {
+    if args.null_output()
+        && (args.color == ColorMode::Always || args.escape == Some(EscapeMode::Yes))
+    {
+        let mut cmd = Args::command();
+        cmd.error(
+            ErrorKind::ArgumentConflict,
+            "Null mode cannot be used together with other rich-format switches",
+        )
+        .exit();
+    }
+
+    if !args.variables.is_empty() && args.key_order != KeyOrder::None {
+        let mut cmd = Args::command();
+        cmd.error(ErrorKind::ArgumentConflict, "VARIABLES cannot be sorted")
+            .exit();
+    }
+}
+
+fn check_exec(args: &Args) {
+    let name = match &args.command {
+        Some(Command::Exec(_)) => "exec",
+        Some(Command::Subst(subst_args)) => {
+            if subst_args.template.is_none() && args.load.iter().any(|path| path.as_os_str() == "-")
+            {
+                let mut cmd = Args::command();
+                cmd.error(
+                    ErrorKind::ArgumentConflict,
+                    "subst reads the template from stdin, so --load - requires a TEMPLATE",
+                )
+                .exit();
+            }
+            "subst"
+        }
+        _ => return,
+    };
+
+    #[cfg(procfs)]
+    let groups = args.all
+        || args.tree.is_some()
+        || args.find.is_some()
+        || args.blame
+        || args.all_matches
+        || args.context;
+    #[cfg(not(procfs))]
+    let groups = false;
+    if groups
+        || args.matrix
+        || args.watch
+        || args.save_snapshot.is_some()
+        || args.against_snapshot.is_some()
+        || !args.variables.is_empty()
+    {
+        let mut cmd = Args::command();
+        cmd.error(
+            ErrorKind::ArgumentConflict,
+            format!("{name} takes the environment of a single source, and cannot be used together with other modes"),
+        )
+        .exit();
+    }
+}
+
+/// Subcommands which read no source
+#[cfg(target_family = "unix")]
+fn check_standalone(args: &Args, sources: usize) {
+    let name = match args.command {
+        Some(Command::Capture(_)) => "capture",
+        Some(Command::Hook(_)) => "hook",
+        Some(Command::Export(_)) => "export",
+        Some(Command::Allow(_)) => "allow",
+        Some(Command::History(_)) => "history",
+        Some(Command::Record(_)) => "record",
+        _ => return,
+    };
+    if sources > 0
+        || args.via.is_some()
+        || args.remote.is_some()
+        || args.matrix
+        || args.watch
+        || args.null
+        || args.resolve_file_refs
+        || !args.variables.is_empty()
+    {
+        let mut cmd = Args::command();
+        cmd.error(
+            ErrorKind::ArgumentConflict,
+            format!("{name} cannot be used together with other sources or modes"),
+        )
+        .exit();
+    }
+}
+
+#[cfg(target_family = "unix")]
+fn check_remote(args: &Args) {
+    if args.remote.is_none() {
+        return;
+    }
+
+    #[cfg(procfs)]
+    let local = args.all
+        || args.tree.is_some()
+        || args.find.is_some()
+        || args.blame
+        || args.all_matches
+        || args.pid.len() + args.selectors().len() > 1
+        || args
+            .selectors()
+            .iter()
+            .any(|selector| !matches!(selector, Selector::Name(_)))
+        || args.pid_ns == PidNamespace::Container;
+    #[cfg(all(remote_env, not(procfs)))]
+    let local = args.pid.len() > 1;
+    #[cfg(not(remote_env))]
+    let local = false;
+
+    if local
+        || args.via.is_some()
+        || !args.load.is_empty()
+        || !args.core.is_empty()
+        || args.command.is_some()
+    {
+        let mut cmd = Args::command();
+        cmd.error(
+            ErrorKind::ArgumentConflict,
+            "--remote asks for a single process by --pid or --name, and cannot be used together with other sources or subcommands",
+        )
+        .exit();
+    }
+}
+
+fn check_watch(args: &Args, sources: usize) {
+    if !args.watch {
+        return;
+    }
+
+    if args.null
+        || args.matrix
+        || args.save_snapshot.is_some()
+        || args.against_snapshot.is_some()
+        || args.command.is_some()
+    {
+        let mut cmd = Args::command();
+        cmd.error(
+            ErrorKind::ArgumentConflict,
+            "--watch cannot be used together with --null, --matrix, snapshots or subcommands",
+        )
+        .exit();
+    }
+
+    #[cfg(procfs)]
+    let sources = sources + args.selectors().len();
+    #[cfg(target_family = "unix")]
+    let sources = sources + usize::from(args.remote.is_some());
+    if sources + usize::from(args.via.is_some()) == 0 {
+        let mut cmd = Args::command();
+        cmd.error(
+            ErrorKind::MissingRequiredArgument,
+            "--watch needs another process or a file to read from",
+        )
+        .exit();
+    }
+}
@@ -0,0 +1,80 @@
+use serde::Serialize;
+
+use crate::definition::AppResult;
+use crate::diff::EnvDiff;
+use crate::env::Env;
+use crate::procfs::Procfs;
+
+/// What a process changed in the environment it inherited from its parent
+#[derive(Debug, Serialize)]
+pub struct Blame {
+    pub pid: u32,
+    pub comm: String,
+    /// `None` when the environment of the process cannot be read
+    pub changes: Option<EnvDiff>,
+}
+
+/// `pid` and its ancestors, starting from the outermost one
+pub fn ancestors(procfs: &Procfs, pid: u32) -> AppResult<Vec<(u32, String)>> {
+    let mut chain = Vec::new();
+    let mut current = pid;
+    loop {
+        let stat = procfs.stat(current)?;
+        chain.push((current, stat.comm));
+        // PID 0 is the parent of init and kthreadd, or of every process outside our PID namespace
+        if stat.ppid == 0 || chain.iter().any(|(pid, _)| *pid == stat.ppid) {
+            break;
+        }
+        current = stat.ppid;
+    }
+    chain.reverse();
+    Ok(chain)
+}
+
+/// Walk from the outermost ancestor down to `pid`, comparing each environment with the last readable one
+pub fn blame(procfs: &Procfs, pid: u32, variables: &[String]) -> AppResult<Vec<Blame>> {
+    let mut inherited = Env(Vec::new());
+    let mut result = Vec::new();
+    for (pid, comm) in ancestors(procfs, pid)? {
+        let changes = crate::remote_linux_procfs::get_environment_string(procfs, pid)
+            .ok()
+            .map(|env_string| {
+                let mut env = Env::from(env_string);
+                if !variables.is_empty() {
+                    env.filter_keys(variables);
+                }
+                let diff = EnvDiff::new(&inherited, &env);
+                inherited = env;
+                diff
+            });
+        result.push(Blame { pid, comm, changes });
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ancestors, blame};
+    use crate::procfs::Procfs;
+
+    #[test]
+    fn ancestors_of_self() {
+        let procfs = Procfs::default();
+        let chain = ancestors(&procfs, std::process::id()).unwrap();
+        let pids: Vec<u32> = chain.iter().map(|(pid, _)| *pid).collect();
+        assert_eq!(pids.last(), Some(&std::process::id()));
+        assert_eq!(
+            pids.iter().rev().nth(1),
+            Some(&std::os::unix::process::parent_id())
+        );
+    }
+
+    #[test]
+    fn blame_of_self() {
+        let procfs = Procfs::default();
+        let chain = blame(&procfs, std::process::id(), &[]).unwrap();
+        let last = chain.last().unwrap();
+        assert_eq!(last.pid, std::process::id());
+        assert!(last.changes.is_some());
+    }
+}
@@ -0,0 +1,105 @@
+use std::os::fd::AsFd;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::args::CaptureArgs;
+use crate::definition::{AppError, AppResult};
+use crate::env::Env;
+use crate::shell::quote;
+
+/// Shell, script and arguments of the script. `SHELL -c SCRIPT [ARG]...` is taken as is,
+/// anything else is a command for `default_shell`, whose words are quoted to reach it unchanged
+fn split_command<'a>(
+    words: &'a [String],
+    default_shell: &'a str,
+) -> (&'a str, String, &'a [String]) {
+    match words {
+        [shell, flag, script, arguments @ ..] if flag == "-c" => (shell, script.clone(), arguments),
+        words => (
+            default_shell,
+            words
+                .iter()
+                .map(|word| quote(word))
+                .collect::<Vec<_>>()
+                .join(" "),
+            &[],
+        ),
+    }
+}
+
+/// Environments of the shell right before and right after sourcing the script.
+/// Both are dumped by printenv2 itself from within the shell, so variables the shell sets on its own cancel out.
+pub fn capture(capture_args: &CaptureArgs) -> AppResult<(Env, Env)> {
+    let exe = std::env::current_exe()?;
+    let exe = quote(&exe.to_string_lossy());
+
+    // Only the current user may write the dumps
+    let dir = std::env::temp_dir().join(format!(
+        "printenv2-capture-{}-{}",
+        std::process::id(),
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos())
+    ));
+    std::fs::DirBuilder::new().mode(0o700).create(&dir)?;
+    let result = run(capture_args, &exe, &dir);
+    let _ = std::fs::remove_dir_all(&dir);
+    result
+}
+
+fn run(capture_args: &CaptureArgs, exe: &str, dir: &Path) -> AppResult<(Env, Env)> {
+    let before = dir.join("before");
+    let after = dir.join("after");
+    let (shell, script, arguments) = split_command(&capture_args.script, &capture_args.shell);
+    let wrapped = format!(
+        "{exe} --null > {}\n{script}\n{exe} --null > {}",
+        quote(&before.to_string_lossy()),
+        quote(&after.to_string_lossy()),
+    );
+
+    // Output of the script goes to stderr as it is written, to keep stdout for the changes
+    let stderr = std::io::stderr().as_fd().try_clone_to_owned()?;
+    let status = Command::new(shell)
+        .arg("-c")
+        .arg(&wrapped)
+        .args(arguments)
+        .stdin(Stdio::inherit())
+        .stdout(stderr)
+        .stderr(Stdio::inherit())
+        .status()?;
+
+    let read = |path: &Path| std::fs::read(path).ok().map(Env::from);
+    match (read(&before), read(&after)) {
+        (Some(before), Some(after)) => {
+            if !status.success() {
+                eprintln!("printenv2: the script finished with {status}");
+            }
+            Ok((before, after))
+        }
+        _ => Err(AppError::Transport(format!(
+            "`{shell} -c {script}` exited with {status} before its environment could be read"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::split_command;
+
+    #[test]
+    fn shell_command() {
+        let words = ["bash", "-c", "source x", "arg0"].map(str::to_owned);
+        let (shell, script, arguments) = split_command(&words, "sh");
+        assert_eq!((shell, script.as_str()), ("bash", "source x"));
+        assert_eq!(arguments, ["arg0"]);
+
+        let words = [".", "./my setup.sh", "it's", "$HOME"].map(str::to_owned);
+        let (shell, script, arguments) = split_command(&words, "sh");
+        assert_eq!(
+            (shell, script.as_str()),
+            ("sh", r"'.' './my setup.sh' 'it'\''s' '$HOME'")
+        );
+        assert!(arguments.is_empty());
+    }
+}
@@ -0,0 +1,108 @@
+use serde::Serialize;
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::definition::AppResult;
+use crate::procfs::Procfs;
+
+/// Set by the kernel for setuid, setgid and capability-raising executions, where the loader ignores `LD_*` variables
+const AT_SECURE: usize = 23;
+
+/// What a process is, besides its environment
+#[derive(Debug, Serialize)]
+pub struct Context {
+    pub pid: u32,
+    pub comm: String,
+    pub argv: Option<Vec<String>>,
+    pub exe: Option<String>,
+    pub cwd: Option<String>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub cgroups: Vec<String>,
+    /// ID of the container, when a cgroup path carries one
+    pub container: Option<String>,
+    pub start_time: Option<String>,
+    /// `AT_SECURE` from the auxiliary vector
+    pub secure: Option<bool>,
+}
+
+impl Context {
+    /// Fields which cannot be read, usually for lack of privileges, are left empty
+    pub fn new(procfs: &Procfs, pid: u32) -> AppResult<Self> {
+        let stat = procfs.stat(pid)?;
+        let status = procfs.status(pid).ok();
+        // cgroup v1 lists a path per hierarchy, which are mostly the same
+        let mut cgroups: Vec<String> = Vec::new();
+        for cgroup in procfs.cgroups(pid).unwrap_or_default() {
+            if !cgroups.contains(&cgroup) {
+                cgroups.push(cgroup);
+            }
+        }
+        let start_time = procfs.boot_time().ok().and_then(|boot_time| {
+            let ticks = u64::try_from(unsafe { libc::sysconf(libc::_SC_CLK_TCK) }).ok()?;
+            let offset = Duration::from_millis(stat.start_time * 1000 / ticks.max(1));
+            Some(crate::timestamp::rfc3339(
+                UNIX_EPOCH + Duration::from_secs(boot_time) + offset,
+            ))
+        });
+
+        Ok(Self {
+            pid,
+            comm: stat.comm,
+            argv: procfs.argv(pid).ok(),
+            exe: procfs.exe(pid).ok().map(|exe| exe.display().to_string()),
+            cwd: procfs.cwd(pid).ok().map(|cwd| cwd.display().to_string()),
+            uid: status.as_ref().map(|status| status.uid),
+            gid: status.as_ref().map(|status| status.gid),
+            container: cgroups.iter().find_map(|cgroup| container_id(cgroup)),
+            cgroups,
+            start_time,
+            secure: procfs.auxv(pid).ok().map(|auxv| {
+                auxv.iter()
+                    .any(|(kind, value)| *kind == AT_SECURE && *value != 0)
+            }),
+        })
+    }
+}
+
+/// Docker, containerd and Podman name cgroups after the 64 hex digit ID of the container,
+/// for example `/system.slice/docker-<ID>.scope` or `/kubepods/burstable/pod<UID>/<ID>`
+fn container_id(cgroup: &str) -> Option<String> {
+    cgroup
+        .split(|c: char| !c.is_ascii_hexdigit())
+        .find(|part| part.len() == 64)
+        .map(str::to_owned)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Context, container_id};
+    use crate::procfs::Procfs;
+
+    #[test]
+    fn container() {
+        let id = "4f3b2a1c".repeat(8);
+        assert_eq!(
+            container_id(&format!("/system.slice/docker-{id}.scope")),
+            Some(id.clone())
+        );
+        assert_eq!(
+            container_id(&format!("/kubepods/burstable/pod1234-abcd/{id}")),
+            Some(id)
+        );
+        assert_eq!(
+            container_id("/user.slice/user-1000.slice/session-2.scope"),
+            None
+        );
+    }
+
+    #[test]
+    fn own_context() {
+        let context = Context::new(&Procfs::default(), std::process::id()).unwrap();
+        assert_eq!(
+            context.cwd,
+            Some(std::env::current_dir().unwrap().display().to_string())
+        );
+        assert_eq!(context.secure, Some(false));
+        assert!(context.start_time.is_some());
+    }
+}
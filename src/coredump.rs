@@ -0,0 +1,497 @@
+use std::path::{Path, PathBuf};
+
+use crate::definition::{AppError, AppResult};
+
+const ET_CORE: u16 = 4;
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+const SHT_SYMTAB: u32 = 2;
+const SHT_DYNSYM: u32 = 11;
+const NT_AUXV: u32 = 6;
+const NT_FILE: u32 = 0x4649_4c45;
+
+/// Names under which C libraries keep the pointer to the live environment
+const ENVIRON_SYMBOLS: [&[u8]; 3] = [b"__environ", b"environ", b"_environ"];
+/// Bounds the walk of an environ array in a corrupted core
+const MAX_VARIABLES: usize = 1 << 16;
+
+fn invalid(path: &Path, reason: &str) -> AppError {
+    AppError::InvalidCore(format!("{}: {reason}", path.display()))
+}
+
+fn u16_at(data: &[u8], offset: u64) -> Option<u16> {
+    let offset = usize::try_from(offset).ok()?;
+    Some(u16::from_le_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn u32_at(data: &[u8], offset: u64) -> Option<u32> {
+    let offset = usize::try_from(offset).ok()?;
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn u64_at(data: &[u8], offset: u64) -> Option<u64> {
+    let offset = usize::try_from(offset).ok()?;
+    Some(u64::from_le_bytes(
+        data.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+fn slice(data: &[u8], offset: u64, len: u64) -> Option<&[u8]> {
+    let start = usize::try_from(offset).ok()?;
+    let end = start.checked_add(usize::try_from(len).ok()?)?;
+    data.get(start..end)
+}
+
+/// Type, name and descriptor of a note
+type Note<'a> = (u32, &'a [u8], &'a [u8]);
+
+struct Segment {
+    kind: u32,
+    offset: u64,
+    vaddr: u64,
+    filesz: u64,
+}
+
+struct Section {
+    kind: u32,
+    offset: u64,
+    size: u64,
+    link: u32,
+}
+
+/// A 64-bit little-endian ELF file, which covers cores of `x86_64` and `aarch64`
+struct Elf<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Elf<'a> {
+    fn parse(data: &'a [u8]) -> Option<Self> {
+        // Magic, 64-bit class, little endian
+        (data.get(..6)? == b"\x7fELF\x02\x01").then_some(Self { data })
+    }
+
+    fn kind(&self) -> Option<u16> {
+        u16_at(self.data, 16)
+    }
+
+    fn segments(&self) -> Option<Vec<Segment>> {
+        let offset = u64_at(self.data, 32)?;
+        let size = u64::from(u16_at(self.data, 54)?);
+        (0..u64::from(u16_at(self.data, 56)?))
+            .map(|i| {
+                let header = offset + i * size;
+                Some(Segment {
+                    kind: u32_at(self.data, header)?,
+                    offset: u64_at(self.data, header + 8)?,
+                    vaddr: u64_at(self.data, header + 16)?,
+                    filesz: u64_at(self.data, header + 32)?,
+                })
+            })
+            .collect()
+    }
+
+    fn sections(&self) -> Option<Vec<Section>> {
+        let offset = u64_at(self.data, 40)?;
+        let size = u64::from(u16_at(self.data, 58)?);
+        (0..u64::from(u16_at(self.data, 60)?))
+            .map(|i| {
+                let header = offset + i * size;
+                Some(Section {
+                    kind: u32_at(self.data, header + 4)?,
+                    offset: u64_at(self.data, header + 24)?,
+                    size: u64_at(self.data, header + 32)?,
+                    link: u32_at(self.data, header + 40)?,
+                })
+            })
+            .collect()
+    }
+
+    fn notes(&self) -> Option<Vec<Note<'a>>> {
+        let pad = |len: u64| len.next_multiple_of(4);
+        let mut notes = Vec::new();
+        for segment in self.segments()?.iter().filter(|s| s.kind == PT_NOTE) {
+            let data = slice(self.data, segment.offset, segment.filesz)?;
+            let mut offset = 0;
+            while offset + 12 <= segment.filesz {
+                let name_size = u64::from(u32_at(data, offset)?);
+                let desc_size = u64::from(u32_at(data, offset + 4)?);
+                let kind = u32_at(data, offset + 8)?;
+                let name = slice(data, offset + 12, name_size)?;
+                let desc = slice(data, offset + 12 + pad(name_size), desc_size)?;
+                notes.push((kind, name, desc));
+                offset += 12 + pad(name_size) + pad(desc_size);
+            }
+        }
+        Some(notes)
+    }
+
+    /// Value of the first defined symbol with one of `names`, in either the static or the dynamic symbol table
+    fn symbol(&self, names: &[&[u8]]) -> Option<u64> {
+        let sections = self.sections()?;
+        sections
+            .iter()
+            .filter(|section| section.kind == SHT_SYMTAB || section.kind == SHT_DYNSYM)
+            .find_map(|table| {
+                let strings = sections.get(usize::try_from(table.link).ok()?)?;
+                let strings = slice(self.data, strings.offset, strings.size)?;
+                (0..table.size / 24).find_map(|i| {
+                    let symbol = table.offset + i * 24;
+                    let name = usize::try_from(u32_at(self.data, symbol)?).ok()?;
+                    let name = strings.get(name..)?.split(|c| *c == 0).next()?;
+                    let section = u16_at(self.data, symbol + 6)?;
+                    let value = u64_at(self.data, symbol + 8)?;
+                    (section != 0 && value != 0 && names.contains(&name)).then_some(value)
+                })
+            })
+    }
+}
+
+/// Memory of the crashed process, as far as it was dumped
+struct Memory<'a> {
+    data: &'a [u8],
+    segments: Vec<Segment>,
+}
+
+impl Memory<'_> {
+    /// Dumped bytes from `address` to the end of its segment
+    fn from(&self, address: u64) -> Option<&[u8]> {
+        let segment = self.segments.iter().find(|segment| {
+            segment.kind == PT_LOAD
+                && segment.vaddr <= address
+                && address - segment.vaddr < segment.filesz
+        })?;
+        let skip = address - segment.vaddr;
+        slice(self.data, segment.offset + skip, segment.filesz - skip)
+    }
+
+    fn u64(&self, address: u64) -> Option<u64> {
+        u64_at(self.from(address)?, 0)
+    }
+
+    fn c_str(&self, address: u64) -> Option<&[u8]> {
+        let bytes = self.from(address)?;
+        bytes.get(..bytes.iter().position(|c| *c == 0)?)
+    }
+
+    /// Strings of a NULL-terminated array of pointers
+    fn strings(&self, mut address: u64) -> Option<Vec<&[u8]>> {
+        let mut strings = Vec::new();
+        loop {
+            match self.u64(address)? {
+                0 => return Some(strings),
+                _ if strings.len() >= MAX_VARIABLES => return None,
+                pointer => strings.push(self.c_str(pointer)?),
+            }
+            address += 8;
+        }
+    }
+}
+
+/// A file mapped into the crashed process, from the `NT_FILE` note
+struct MappedFile {
+    start: u64,
+    file_offset: u64,
+    path: PathBuf,
+}
+
+fn mapped_files(desc: &[u8]) -> Option<Vec<MappedFile>> {
+    let count = u64_at(desc, 0)?;
+    let page_size = u64_at(desc, 8)?;
+    // Entries of start, end and file offset in pages are followed by as many file names
+    let names_at = usize::try_from(count.checked_mul(24)?.checked_add(16)?).ok()?;
+    let mut names = desc.get(names_at..)?.split(|c| *c == 0);
+    (0..count)
+        .map(|i| {
+            let entry = 16 + i * 24;
+            let name = names.next()?;
+            Some(MappedFile {
+                start: u64_at(desc, entry)?,
+                file_offset: u64_at(desc, entry + 16)?.checked_mul(page_size)?,
+                path: PathBuf::from(String::from_utf8_lossy(name).into_owned()),
+            })
+        })
+        .collect()
+}
+
+/// The environment as the process started with. The kernel copies the auxiliary vector onto the
+/// initial stack right after the NULL ending the envp array, so finding it there leads to envp
+fn initial_environment<'a>(memory: &'a Memory, auxv: &[u8]) -> Option<Vec<&'a [u8]>> {
+    let auxv_address = memory
+        .segments
+        .iter()
+        .filter(|segment| segment.kind == PT_LOAD)
+        .find_map(|segment| {
+            let data = slice(memory.data, segment.offset, segment.filesz)?;
+            let position = (0..=data.len().checked_sub(auxv.len())?)
+                .step_by(8)
+                .find(|i| data[*i..].starts_with(auxv))?;
+            Some(segment.vaddr + u64::try_from(position).ok()?)
+        })?;
+
+    let envp_end = auxv_address.checked_sub(8)?;
+    if memory.u64(envp_end)? != 0 {
+        return None;
+    }
+    let mut envp = envp_end;
+    while memory.u64(envp.checked_sub(8)?)? != 0 {
+        envp -= 8;
+    }
+    memory.strings(envp)
+}
+
+/// The environment at the time of the crash, through the `environ` symbol of a mapped object.
+/// Needs the very files the process had mapped, with their symbol tables
+fn live_environment<'a>(memory: &'a Memory, files: &[MappedFile]) -> Option<Vec<&'a [u8]>> {
+    files
+        .iter()
+        .filter(|file| file.file_offset == 0)
+        .find_map(|file| {
+            let data = std::fs::read(&file.path).ok()?;
+            let elf = Elf::parse(&data)?;
+            let value = elf.symbol(&ENVIRON_SYMBOLS)?;
+            // Objects are mapped at their lowest address plus a bias, which is zero for non-PIE executables
+            let lowest = elf
+                .segments()?
+                .iter()
+                .filter(|segment| segment.kind == PT_LOAD)
+                .map(|segment| segment.vaddr & !0xfff)
+                .min()?;
+            let environ = memory.u64(file.start.checked_sub(lowest)?.checked_add(value)?)?;
+            let strings = memory.strings(environ).filter(|strings| {
+                !strings.is_empty() && strings.iter().all(|string| string.contains(&b'='))
+            })?;
+            Some(strings)
+        })
+}
+
+/// Environment variables of a crashed process from its core file, in the format of --null output.
+/// Prefers the environment at the time of the crash, and falls back to the initial one
+pub fn read_environment(path: &Path) -> AppResult<Vec<u8>> {
+    let data = std::fs::read(path)?;
+    let elf =
+        Elf::parse(&data).ok_or_else(|| invalid(path, "not a 64-bit little-endian ELF file"))?;
+    if elf.kind() != Some(ET_CORE) {
+        return Err(invalid(path, "not a core file"));
+    }
+    let malformed = || invalid(path, "malformed core file");
+    let notes = elf.notes().ok_or_else(malformed)?;
+    let memory = Memory {
+        data: &data,
+        segments: elf.segments().ok_or_else(malformed)?,
+    };
+
+    let files = notes
+        .iter()
+        .find(|(kind, name, _)| *kind == NT_FILE && name.starts_with(b"CORE"))
+        .and_then(|(_, _, desc)| mapped_files(desc))
+        .unwrap_or_default();
+    let initial = || {
+        let (_, _, auxv) = notes
+            .iter()
+            .find(|(kind, name, _)| *kind == NT_AUXV && name.starts_with(b"CORE"))
+            .ok_or_else(|| invalid(path, "no auxiliary vector"))?;
+        initial_environment(&memory, auxv).ok_or_else(|| invalid(path, "initial stack not found"))
+    };
+    let strings = live_environment(&memory, &files).map_or_else(initial, Ok)?;
+
+    Ok(strings.iter().fold(Vec::new(), |mut environment, string| {
+        environment.extend_from_slice(string);
+        environment.push(0);
+        environment
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::read_environment;
+
+    const STACK: u64 = 0x7fff_0000;
+    const BASE: u64 = 0x1000_0000;
+    const HEAP: u64 = 0x2000_0000;
+
+    /// A core file with the given notes and memory segments
+    fn core_file(notes: &[(u32, Vec<u8>)], segments: &[(u64, Vec<u8>)]) -> Vec<u8> {
+        let mut note = Vec::new();
+        for (kind, desc) in notes {
+            note.extend(5u32.to_le_bytes());
+            note.extend(u32::try_from(desc.len()).unwrap().to_le_bytes());
+            note.extend(kind.to_le_bytes());
+            note.extend(b"CORE\0\0\0\0");
+            note.extend(desc);
+            note.resize(note.len().next_multiple_of(4), 0);
+        }
+
+        let phnum = 1 + segments.len();
+        let mut offset = 64 + 56 * phnum;
+        let mut headers: Vec<u8> = Vec::new();
+        let mut body: Vec<u8> = Vec::new();
+        for (kind, vaddr, data) in
+            std::iter::once((4u32, 0, &note)).chain(segments.iter().map(|(v, d)| (1, *v, d)))
+        {
+            headers.extend(kind.to_le_bytes());
+            headers.extend(0u32.to_le_bytes());
+            for field in [
+                offset,
+                usize::try_from(vaddr).unwrap(),
+                0,
+                data.len(),
+                data.len(),
+                0,
+            ] {
+                headers.extend((field as u64).to_le_bytes());
+            }
+            offset += data.len();
+            body.extend(data);
+        }
+
+        let mut core = elf_header(4, phnum, 0);
+        core.extend(headers);
+        core.extend(body);
+        core
+    }
+
+    fn elf_header(kind: u16, phnum: usize, shoff: u64) -> Vec<u8> {
+        let mut header = b"\x7fELF\x02\x01\x01".to_vec();
+        header.resize(16, 0);
+        header.extend(kind.to_le_bytes());
+        header.extend(62u16.to_le_bytes());
+        header.extend(1u32.to_le_bytes());
+        header.extend(0u64.to_le_bytes());
+        header.extend(64u64.to_le_bytes());
+        header.extend(shoff.to_le_bytes());
+        header.extend(0u32.to_le_bytes());
+        let shnum = if shoff == 0 { 0 } else { 3 };
+        for field in [64u16, 56, u16::try_from(phnum).unwrap(), 64, shnum, 0] {
+            header.extend(field.to_le_bytes());
+        }
+        header
+    }
+
+    fn write_core(name: &str, core: &[u8]) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("printenv2-{}-{name}", std::process::id()));
+        std::fs::write(&path, core).unwrap();
+        path
+    }
+
+    #[test]
+    fn initial_stack() {
+        let auxv: Vec<u8> = [6u64, 4096, 0, 0]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let strings = b"prog\0A=1\0B=two\0";
+        let strings_at = STACK + 8 * 6 + auxv.len() as u64;
+        let mut stack: Vec<u8> = [1, strings_at, 0, strings_at + 5, strings_at + 9, 0]
+            .iter()
+            .flat_map(|v: &u64| v.to_le_bytes())
+            .collect();
+        stack.extend(&auxv);
+        stack.extend(strings);
+
+        let path = write_core(
+            "initial.core",
+            &core_file(&[(super::NT_AUXV, auxv)], &[(STACK, stack)]),
+        );
+        let environment = read_environment(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(environment.unwrap(), b"A=1\0B=two\0");
+    }
+
+    #[test]
+    fn auxv_ending_segment() {
+        let auxv: Vec<u8> = [6u64, 4096, 0, 0]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        // Strings are in another segment, so that nothing follows the auxiliary vector
+        let mut stack: Vec<u8> = [1, HEAP, 0, HEAP + 5, 0]
+            .iter()
+            .flat_map(|v: &u64| v.to_le_bytes())
+            .collect();
+        stack.extend(&auxv);
+
+        let path = write_core(
+            "auxv-last.core",
+            &core_file(
+                &[(super::NT_AUXV, auxv)],
+                &[(STACK, stack), (HEAP, b"prog\0LAST=1\0".to_vec())],
+            ),
+        );
+        let environment = read_environment(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(environment.unwrap(), b"LAST=1\0");
+    }
+
+    #[test]
+    fn live_environ() {
+        // A shared object defining __environ at 0x100, with a symbol table and its strings
+        let strtab = b"\0__environ\0";
+        let mut symtab = vec![0; 24];
+        symtab.extend(1u32.to_le_bytes());
+        symtab.extend([0x11, 0]);
+        symtab.extend(7u16.to_le_bytes());
+        symtab.extend(0x100u64.to_le_bytes());
+        symtab.extend(8u64.to_le_bytes());
+        let data_offset = 64 + 56;
+        let shoff = data_offset + symtab.len() + strtab.len();
+        let mut object = elf_header(3, 1, shoff as u64);
+        object.extend(1u32.to_le_bytes());
+        object.extend(0u32.to_le_bytes());
+        object.extend([0u64; 6].iter().flat_map(|v| v.to_le_bytes()));
+        object.extend(&symtab);
+        object.extend(strtab);
+        let sections = [
+            (0u32, 0usize, 0usize, 0u32),
+            (11, data_offset, symtab.len(), 2),
+            (3, data_offset + symtab.len(), strtab.len(), 0),
+        ];
+        for (kind, offset, size, link) in sections {
+            object.extend(0u32.to_le_bytes());
+            object.extend(kind.to_le_bytes());
+            for field in [0, 0, offset as u64, size as u64] {
+                object.extend(u64::to_le_bytes(field));
+            }
+            object.extend(link.to_le_bytes());
+            object.extend(0u32.to_le_bytes());
+            object.extend([0u64; 2].iter().flat_map(|v| v.to_le_bytes()));
+        }
+        let object_path = write_core("libfake.so", &object);
+
+        // The object is mapped at BASE, and its __environ points to an array on the heap
+        let mut mapping = vec![0; 0x100];
+        mapping.extend(HEAP.to_le_bytes());
+        let mut heap: Vec<u8> = [HEAP + 16, 0]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        heap.extend(b"LIVE=yes\0");
+
+        let mut files: Vec<u8> = [1, 4096, BASE, BASE + 0x1000, 0]
+            .iter()
+            .flat_map(|v: &u64| v.to_le_bytes())
+            .collect();
+        files.extend(object_path.to_str().unwrap().as_bytes());
+        files.push(0);
+
+        let path = write_core(
+            "live.core",
+            &core_file(&[(super::NT_FILE, files)], &[(BASE, mapping), (HEAP, heap)]),
+        );
+        let environment = read_environment(&path);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&object_path).unwrap();
+        assert_eq!(environment.unwrap(), b"LIVE=yes\0");
+    }
+
+    #[test]
+    fn not_a_core() {
+        let path = write_core("not.core", b"\x7fELF\x02\x01\x01");
+        let result = read_environment(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+    }
+}
@@ -1,9 +1,30 @@
+use std::fmt;
+
 #[derive(Debug)]
 #[allow(dead_code)]
 pub enum AppError {
     Utf8Error(std::str::Utf8Error),
     StdIo(std::io::Error),
     SerdeJson(serde_json::Error),
+    Procfs(String),
+    InvalidPid(String),
+    NoProcessMatched(String),
+    AmbiguousProcess(Vec<(u32, String)>),
+    InvalidPattern(String),
+    InvalidSnapshot(String),
+    /// The process exited, or its PID was reused by another process, while being read
+    ProcessGone(u32),
+    /// A command run to read an environment, such as the one given with --via, failed
+    Transport(String),
+    /// An agent refused or failed to answer
+    Remote(String),
+    InvalidCore(String),
+    InvalidDotenv(String),
+    InvalidTemplate(String),
+    /// A template referenced variables which are not set
+    UndefinedVariable(String),
+    /// What was being done when the inner error happened
+    Context(String, Box<Self>),
 
     #[cfg(windows)]
     WindowsCore(windows::core::Error),
@@ -26,6 +47,155 @@ pub enum AppError {
 
 pub type AppResult<T> = Result<T, AppError>;
 
+/// Classes of failures, each with its own exit status
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// Invalid arguments, as reported by clap
+    Usage,
+    /// The process or file does not exist, or is gone
+    NotFound,
+    PermissionDenied,
+    /// A file, snapshot, core or procfs entry is malformed
+    Parse,
+    /// Some processes of a group could not be read
+    PartialOutput,
+    Other,
+}
+
+impl ErrorKind {
+    /// Exit status; 1 is left for missing variables and differences
+    pub const fn exit_code(self) -> i32 {
+        match self {
+            Self::Usage => 2,
+            Self::NotFound => 3,
+            Self::PermissionDenied => 4,
+            Self::Parse => 5,
+            Self::PartialOutput => 6,
+            Self::Other => 7,
+        }
+    }
+}
+
+impl AppError {
+    /// Wrap the error with what was being done
+    pub fn context(self, context: impl Into<String>) -> Self {
+        Self::Context(context.into(), Box::new(self))
+    }
+
+    /// The error without any context
+    pub fn root(&self) -> &Self {
+        match self {
+            Self::Context(_, err) => err.root(),
+            err => err,
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self.root() {
+            Self::StdIo(err) => io_kind(err),
+            Self::SerdeJson(err) if err.is_io() => ErrorKind::Other,
+            Self::Utf8Error(_)
+            | Self::SerdeJson(_)
+            | Self::Procfs(_)
+            | Self::InvalidSnapshot(_)
+            | Self::InvalidCore(_)
+            | Self::InvalidDotenv(_)
+            | Self::InvalidTemplate(_) => ErrorKind::Parse,
+            Self::InvalidPid(_) | Self::InvalidPattern(_) | Self::AmbiguousProcess(_) => {
+                ErrorKind::Usage
+            }
+            Self::NoProcessMatched(_) | Self::ProcessGone(_) | Self::UndefinedVariable(_) => {
+                ErrorKind::NotFound
+            }
+            #[cfg(windows)]
+            Self::WindowsCore(err) if err.code() == windows::Win32::Foundation::E_ACCESSDENIED => {
+                ErrorKind::PermissionDenied
+            }
+            _ => ErrorKind::Other,
+        }
+    }
+
+    /// A suggestion on how to get past the error, when there is an obvious one
+    pub fn hint(&self) -> Option<String> {
+        match self.root() {
+            Self::AmbiguousProcess(processes) => processes.first().map(|(pid, _)| {
+                format!("pick one with --pid, such as --pid {pid}, or read all of them with --all-matches")
+            }),
+            Self::InvalidPattern(_) => {
+                Some("patterns are globs, unless --regex is given".to_owned())
+            }
+            Self::ProcessGone(_) => Some(
+                "the process exited or its PID was reused while being read; try again".to_owned(),
+            ),
+            err if err.kind() == ErrorKind::PermissionDenied => Some(
+                "reading processes of other users requires root, or CAP_SYS_PTRACE on Linux"
+                    .to_owned(),
+            ),
+            _ => None,
+        }
+    }
+}
+
+fn io_kind(err: &std::io::Error) -> ErrorKind {
+    match err.kind() {
+        std::io::ErrorKind::NotFound => ErrorKind::NotFound,
+        std::io::ErrorKind::PermissionDenied => ErrorKind::PermissionDenied,
+        std::io::ErrorKind::InvalidData | std::io::ErrorKind::UnexpectedEof => ErrorKind::Parse,
+        #[cfg(unix)]
+        _ if err.raw_os_error() == Some(libc::ESRCH) => ErrorKind::NotFound,
+        _ => ErrorKind::Other,
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Utf8Error(err) => write!(f, "invalid UTF-8: {err}"),
+            Self::StdIo(err) => write!(f, "{err}"),
+            Self::SerdeJson(err) => write!(f, "invalid JSON: {err}"),
+            Self::Procfs(message) => write!(f, "procfs: {message}"),
+            Self::InvalidPid(pid) => write!(f, "invalid PID: {pid}"),
+            Self::NoProcessMatched(selector) => write!(f, "no process matched {selector}"),
+            Self::AmbiguousProcess(processes) => {
+                write!(f, "{} processes matched:", processes.len())?;
+                for (pid, comm) in processes {
+                    write!(f, " {pid} ({comm})")?;
+                }
+                Ok(())
+            }
+            Self::InvalidPattern(message) => write!(f, "invalid pattern: {message}"),
+            Self::InvalidSnapshot(message) => write!(f, "invalid snapshot: {message}"),
+            Self::ProcessGone(pid) => write!(f, "process {pid} is gone"),
+            Self::Transport(message) => write!(f, "{message}"),
+            Self::Remote(message) => write!(f, "the agent answered: {message}"),
+            Self::InvalidCore(message) => write!(f, "invalid core file: {message}"),
+            Self::InvalidDotenv(message) => write!(f, "invalid dotenv file: {message}"),
+            Self::InvalidTemplate(message) => write!(f, "invalid template: {message}"),
+            Self::UndefinedVariable(message) => write!(f, "{message}"),
+            Self::Context(context, err) => write!(f, "{context}: {err}"),
+
+            #[cfg(windows)]
+            Self::WindowsCore(err) => write!(f, "{err}"),
+            #[cfg(windows)]
+            Self::Utf16Error(err) => write!(f, "invalid UTF-16: {err}"),
+            #[cfg(any(windows, unix_kvm))]
+            Self::NulError(err) => write!(f, "{err}"),
+
+            #[cfg(unix_kvm)]
+            Self::UnixErrorString(message) => write!(f, "kvm: {}", message.to_string_lossy()),
+
+            #[cfg(any(unix_kvm, unix_apple_sysctl))]
+            Self::TryFromIntError(err) => write!(f, "{err}"),
+
+            #[cfg(unix_apple_sysctl)]
+            Self::TryFromSliceError(err) => write!(f, "malformed sysctl output: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
 impl From<std::str::Utf8Error> for AppError {
     fn from(err: std::str::Utf8Error) -> Self {
         Self::Utf8Error(err)
@@ -92,3 +262,33 @@ impl From<std::array::TryFromSliceError> for AppError {
         Self::TryFromSliceError(err)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{AppError, ErrorKind};
+
+    #[test]
+    fn kind_and_message() {
+        let denied = AppError::from(std::io::Error::from(std::io::ErrorKind::PermissionDenied))
+            .context("cannot read environment of process 1 through procfs");
+        assert_eq!(denied.kind(), ErrorKind::PermissionDenied);
+        assert_eq!(denied.kind().exit_code(), 4);
+        assert!(
+            denied
+                .to_string()
+                .starts_with("cannot read environment of process 1 through procfs: ")
+        );
+        assert!(denied.hint().is_some());
+
+        assert_eq!(AppError::ProcessGone(7).kind(), ErrorKind::NotFound);
+        assert_eq!(
+            AppError::InvalidSnapshot("bad".to_owned()).kind(),
+            ErrorKind::Parse
+        );
+        assert_eq!(
+            AppError::AmbiguousProcess(vec![(1, "init".to_owned()), (2, "sh".to_owned())])
+                .to_string(),
+            "2 processes matched: 1 (init) 2 (sh)"
+        );
+    }
+}
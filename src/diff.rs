@@ -0,0 +1,223 @@
+use crate::env::{Env, RecordPair};
+use crate::platform_ext::u8_vec_to_string;
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+
+/// Differences between two environments, each list ordered by key
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct EnvDiff {
+    pub added: Vec<RecordPair>,
+    pub removed: Vec<RecordPair>,
+    /// Key, old value and new value
+    pub changed: Vec<(Vec<u8>, Vec<u8>, Vec<u8>)>,
+}
+
+impl EnvDiff {
+    pub fn new(old: &Env, new: &Env) -> Self {
+        Self::compare(old, new, false)
+    }
+
+    /// Compare two environments, optionally treating keys differing only in ASCII case as the same.
+    /// Reported keys are spelled as in `new`, or in `old` for removed variables
+    pub fn compare<'a>(old: &'a Env, new: &'a Env, ignore_case: bool) -> Self {
+        let normalize = |key: &[u8]| {
+            if ignore_case {
+                key.to_ascii_uppercase()
+            } else {
+                key.to_vec()
+            }
+        };
+        // With duplicated keys, the first occurrence wins, as it does for getenv() and Env::get
+        let index = |env: &'a Env| {
+            let mut records: BTreeMap<Vec<u8>, &'a RecordPair> = BTreeMap::new();
+            for record in env.iter() {
+                records.entry(normalize(&record.0)).or_insert(record);
+            }
+            records
+        };
+        let (old, new) = (index(old), index(new));
+
+        let mut diff = Self::default();
+        for (key, new_record) in &new {
+            match old.get(key) {
+                None => diff
+                    .added
+                    .push(RecordPair(new_record.0.clone(), new_record.1.clone())),
+                Some(old_record) if old_record.1 != new_record.1 => {
+                    diff.changed.push((
+                        new_record.0.clone(),
+                        old_record.1.clone(),
+                        new_record.1.clone(),
+                    ));
+                }
+                Some(_) => (),
+            }
+        }
+        for (key, old_record) in &old {
+            if !new.contains_key(key) {
+                diff.removed
+                    .push(RecordPair(old_record.0.clone(), old_record.1.clone()));
+            }
+        }
+        diff
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// Keep only the differences in variables whose keys satisfy `predicate`
+    pub fn retain_keys<F: Fn(&[u8]) -> bool>(&mut self, predicate: F) {
+        self.added.retain(|record| predicate(&record.0));
+        self.removed.retain(|record| predicate(&record.0));
+        self.changed.retain(|(key, _, _)| predicate(key));
+    }
+}
+
+fn lossy(bytes: &[u8]) -> String {
+    u8_vec_to_string(bytes).unwrap_or_else(|this| this)
+}
+
+struct Records<'a>(&'a [RecordPair]);
+
+impl Serialize for Records<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for RecordPair(k, v) in self.0 {
+            map.serialize_entry(&lossy(k), &lossy(v))?;
+        }
+        map.end()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Change {
+    old: String,
+    new: String,
+}
+
+impl Serialize for EnvDiff {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let changed: BTreeMap<String, Change> = self
+            .changed
+            .iter()
+            .map(|(key, old, new)| {
+                (
+                    lossy(key),
+                    Change {
+                        old: lossy(old),
+                        new: lossy(new),
+                    },
+                )
+            })
+            .collect();
+
+        let mut map = serializer.serialize_map(Some(3))?;
+        map.serialize_entry("added", &Records(&self.added))?;
+        map.serialize_entry("removed", &Records(&self.removed))?;
+        map.serialize_entry("changed", &changed)?;
+        map.end()
+    }
+}
+
+/// The JSON form of `EnvDiff`, as written by its `Serialize` implementation
+#[derive(Deserialize)]
+struct Fields {
+    added: BTreeMap<String, String>,
+    removed: BTreeMap<String, String>,
+    changed: BTreeMap<String, Change>,
+}
+
+impl<'de> Deserialize<'de> for EnvDiff {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let fields = Fields::deserialize(deserializer)?;
+        let records = |map: BTreeMap<String, String>| {
+            map.into_iter()
+                .map(|(k, v)| RecordPair(k.into_bytes(), v.into_bytes()))
+                .collect()
+        };
+        Ok(Self {
+            added: records(fields.added),
+            removed: records(fields.removed),
+            changed: fields
+                .changed
+                .into_iter()
+                .map(|(key, change)| {
+                    (
+                        key.into_bytes(),
+                        change.old.into_bytes(),
+                        change.new.into_bytes(),
+                    )
+                })
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::EnvDiff;
+    use crate::env::{Env, RecordPair};
+
+    #[test]
+    fn added_removed_changed() {
+        let old = Env::from(Vec::from("SAME=1\0GONE=2\0EDIT=3\0"));
+        let new = Env::from(Vec::from("EDIT=4\0SAME=1\0NEW=5\0"));
+        let diff = EnvDiff::new(&old, &new);
+        assert_eq!(
+            diff,
+            EnvDiff {
+                added: vec![RecordPair(b"NEW".to_vec(), b"5".to_vec())],
+                removed: vec![RecordPair(b"GONE".to_vec(), b"2".to_vec())],
+                changed: vec![(b"EDIT".to_vec(), b"3".to_vec(), b"4".to_vec())],
+            }
+        );
+        assert!(!diff.is_empty());
+        assert!(EnvDiff::new(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn duplicated_keys() {
+        let old = Env::from(Vec::from("DUP=first\0DUP=second\0"));
+        let new = Env::from(Vec::from("DUP=first\0DUP=third\0"));
+        assert_eq!(old.get(b"DUP"), Some(&b"first"[..]));
+        assert!(EnvDiff::new(&old, &new).is_empty());
+
+        let new = Env::from(Vec::from("DUP=second\0DUP=first\0"));
+        assert_eq!(
+            EnvDiff::new(&old, &new).changed,
+            vec![(b"DUP".to_vec(), b"first".to_vec(), b"second".to_vec())]
+        );
+    }
+
+    #[test]
+    fn ignore_case() {
+        let old = Env::from(Vec::from("Path=a\0windir=b\0"));
+        let new = Env::from(Vec::from("PATH=a\0WINDIR=c\0"));
+        assert!(!EnvDiff::compare(&old, &new, false).added.is_empty());
+        assert_eq!(
+            EnvDiff::compare(&old, &new, true),
+            EnvDiff {
+                changed: vec![(b"WINDIR".to_vec(), b"b".to_vec(), b"c".to_vec())],
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn json() {
+        let old = Env::from(Vec::from("GONE=2\0EDIT=3\0"));
+        let new = Env::from(Vec::from("EDIT=4\0NEW=5\0"));
+        assert_eq!(
+            serde_json::to_string(&EnvDiff::new(&old, &new)).unwrap(),
+            r#"{"added":{"NEW":"5"},"removed":{"GONE":"2"},"changed":{"EDIT":{"old":"3","new":"4"}}}"#
+        );
+
+        let mut diff = EnvDiff::new(&old, &new);
+        let json = serde_json::to_string(&diff).unwrap();
+        assert_eq!(serde_json::from_str::<EnvDiff>(&json).unwrap(), diff);
+        diff.retain_keys(|key| key != b"GONE");
+        assert!(diff.removed.is_empty() && !diff.changed.is_empty());
+    }
+}
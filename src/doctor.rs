@@ -0,0 +1,303 @@
+use serde::Serialize;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use crate::definition::AppError;
+use crate::procfs::Procfs;
+
+const CAP_SYS_PTRACE: u64 = 1 << 19;
+const KTHREADD_PID: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    Warning,
+    Blocked,
+}
+
+/// Outcome of a single check
+#[derive(Debug, Serialize)]
+pub struct Finding {
+    pub check: &'static str,
+    pub status: Status,
+    pub detail: String,
+}
+
+impl Finding {
+    fn new(check: &'static str, status: Status, detail: impl Into<String>) -> Self {
+        Self {
+            check,
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// Privileges of printenv2 itself
+struct Caller {
+    euid: u32,
+    ptrace: bool,
+}
+
+impl Caller {
+    fn new(procfs: &Procfs) -> Self {
+        let status = procfs.status(procfs.self_pid()).ok();
+        Self {
+            euid: unsafe { libc::geteuid() },
+            ptrace: status.is_some_and(|status| status.cap_effective & CAP_SYS_PTRACE != 0),
+        }
+    }
+
+    const fn privileged(&self) -> bool {
+        self.euid == 0 || self.ptrace
+    }
+}
+
+/// Which ways of reading environments work on this host
+pub fn backends(procfs: &Procfs) -> Vec<Finding> {
+    let mut findings = vec![if procfs.environ(procfs.self_pid()).is_ok() {
+        Finding::new("procfs", Status::Ok, "environments can be read from procfs")
+    } else {
+        Finding::new(
+            "procfs",
+            Status::Blocked,
+            "procfs is not mounted, or environ of printenv2 itself cannot be read",
+        )
+    }];
+    for debugger in ["gdb", "lldb"] {
+        findings.push(find_in_path(debugger).map_or_else(
+            || Finding::new(debugger, Status::Warning, "not found in PATH"),
+            |path| Finding::new(debugger, Status::Ok, path),
+        ));
+    }
+    findings.push(ptrace_scope());
+    findings
+}
+
+fn find_in_path(program: &str) -> Option<String> {
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(program))
+        .find(|path| path.is_file())
+        .map(|path| path.display().to_string())
+}
+
+/// Yama does not guard procfs, but it decides whether debuggers can attach to read the present environment
+fn ptrace_scope() -> Finding {
+    let scope = std::fs::read_to_string("/proc/sys/kernel/yama/ptrace_scope")
+        .ok()
+        .and_then(|scope| scope.trim().parse::<u8>().ok());
+    match scope {
+        None | Some(0) => Finding::new("ptrace_scope", Status::Ok, "debuggers may attach"),
+        Some(1) => Finding::new(
+            "ptrace_scope",
+            Status::Warning,
+            "kernel.yama.ptrace_scope is 1: debuggers may only attach to their descendants without CAP_SYS_PTRACE",
+        ),
+        Some(2) => Finding::new(
+            "ptrace_scope",
+            Status::Warning,
+            "kernel.yama.ptrace_scope is 2: only CAP_SYS_PTRACE allows debuggers to attach",
+        ),
+        Some(scope) => Finding::new(
+            "ptrace_scope",
+            Status::Blocked,
+            format!("kernel.yama.ptrace_scope is {scope}: debuggers cannot attach at all"),
+        ),
+    }
+}
+
+/// Options of the procfs mount, such as `hidepid=2`
+fn hidepid(procfs: &Procfs) -> Option<String> {
+    let mounts = std::fs::read_to_string(procfs.path(procfs.self_pid(), "mounts")).ok()?;
+    mounts.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        (fields.get(2) == Some(&"proc") && Path::new(fields.get(1)?) == procfs.root()).then(
+            || {
+                fields
+                    .get(3)?
+                    .split(',')
+                    .find(|option| option.starts_with("hidepid=") && *option != "hidepid=0")
+                    .map(str::to_owned)
+            },
+        )?
+    })
+}
+
+/// Everything that stands between printenv2 and the environment of `pid`
+pub fn diagnose(procfs: &Procfs, pid: u32) -> Vec<Finding> {
+    let caller = Caller::new(procfs);
+    let mut findings = Vec::new();
+
+    let Ok(stat) = procfs.stat(pid) else {
+        let detail = hidepid(procfs).map_or_else(
+            || format!("process {pid} does not exist"),
+            |option| {
+                format!(
+                    "process {pid} does not exist, or procfs is mounted with {option} which hides processes of other users"
+                )
+            },
+        );
+        findings.push(Finding::new("exists", Status::Blocked, detail));
+        return findings;
+    };
+    findings.push(Finding::new(
+        "exists",
+        Status::Ok,
+        format!("{} ({})", pid, stat.comm),
+    ));
+
+    if stat.state == 'Z' {
+        findings.push(Finding::new(
+            "zombie",
+            Status::Blocked,
+            "the process has exited and released its memory, waiting for its parent to reap it",
+        ));
+    }
+    if pid == KTHREADD_PID || stat.ppid == KTHREADD_PID {
+        findings.push(Finding::new(
+            "kernel thread",
+            Status::Blocked,
+            "kernel threads have no environment",
+        ));
+    }
+
+    if let Ok(status) = procfs.status(pid) {
+        findings.push(ownership(&caller, status.uid, status.euid));
+    }
+
+    // Files of a process which is not dumpable belong to root, whoever runs it
+    if let Ok(metadata) = std::fs::metadata(procfs.path(pid, "environ"))
+        && let Ok(status) = procfs.status(pid)
+        && metadata.uid() == 0
+        && status.uid != 0
+    {
+        findings.push(Finding::new(
+            "dumpable",
+            if caller.privileged() {
+                Status::Ok
+            } else {
+                Status::Blocked
+            },
+            "the process is not dumpable, because it runs a setuid or setgid executable, or called prctl(PR_SET_DUMPABLE, 0)",
+        ));
+    }
+
+    if let Some(option) = hidepid(procfs) {
+        findings.push(Finding::new(
+            "hidepid",
+            Status::Warning,
+            format!("procfs is mounted with {option}, which hides processes of other users"),
+        ));
+    }
+
+    findings.push(match procfs.environ(pid) {
+        Ok(environ) if environ.is_empty() => Finding::new(
+            "environ",
+            Status::Warning,
+            "the environment is empty, as for kernel threads, zombies and processes which cleared it",
+        ),
+        Ok(environ) => Finding::new(
+            "environ",
+            Status::Ok,
+            format!(
+                "{} variables",
+                environ.split(|c| *c == 0).filter(|r| !r.is_empty()).count()
+            ),
+        ),
+        Err(err) => Finding::new("environ", Status::Blocked, err.to_string()),
+    });
+    findings
+}
+
+fn ownership(caller: &Caller, uid: u32, euid: u32) -> Finding {
+    if caller.euid == 0 {
+        return Finding::new("owner", Status::Ok, "printenv2 runs as root");
+    }
+    if uid != euid {
+        return Finding::new(
+            "owner",
+            if caller.ptrace {
+                Status::Ok
+            } else {
+                Status::Blocked
+            },
+            format!("the process runs a setuid executable, as uid {euid} on behalf of uid {uid}"),
+        );
+    }
+    if uid == caller.euid {
+        return Finding::new("owner", Status::Ok, format!("both run as uid {uid}"));
+    }
+    if caller.ptrace {
+        return Finding::new(
+            "owner",
+            Status::Ok,
+            format!("owned by uid {uid}, readable through CAP_SYS_PTRACE"),
+        );
+    }
+    Finding::new(
+        "owner",
+        Status::Blocked,
+        format!(
+            "owned by uid {uid} while printenv2 runs as uid {}; use sudo, or grant CAP_SYS_PTRACE",
+            caller.euid
+        ),
+    )
+}
+
+/// Short explanations of why reading `pid` failed with `err`, to print along with the error
+pub fn hints(procfs: &Procfs, pid: u32, err: &AppError) -> Vec<String> {
+    let relevant = match err.root() {
+        AppError::StdIo(err) => matches!(
+            err.kind(),
+            std::io::ErrorKind::PermissionDenied | std::io::ErrorKind::NotFound
+        ),
+        AppError::ProcessGone(_) => true,
+        _ => false,
+    };
+    if !relevant {
+        return Vec::new();
+    }
+
+    let mut hints: Vec<String> = diagnose(procfs, pid)
+        .into_iter()
+        .filter(|finding| finding.status == Status::Blocked && finding.check != "environ")
+        .map(|finding| finding.detail)
+        .collect();
+    hints.push(format!("run `printenv2 doctor {pid}` for details"));
+    hints
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Caller, Status, diagnose, ownership};
+    use crate::procfs::Procfs;
+
+    #[test]
+    fn owner() {
+        let user = Caller {
+            euid: 1000,
+            ptrace: false,
+        };
+        assert_eq!(ownership(&user, 1000, 1000).status, Status::Ok);
+        assert_eq!(ownership(&user, 1001, 1001).status, Status::Blocked);
+        assert_eq!(ownership(&user, 1000, 0).status, Status::Blocked);
+
+        let tracer = Caller {
+            euid: 1000,
+            ptrace: true,
+        };
+        assert_eq!(ownership(&tracer, 1001, 1001).status, Status::Ok);
+    }
+
+    #[test]
+    fn diagnose_self() {
+        let findings = diagnose(&Procfs::default(), std::process::id());
+        assert!(
+            findings
+                .iter()
+                .all(|finding| finding.status != Status::Blocked)
+        );
+        assert!(findings.iter().any(|finding| finding.check == "environ"));
+    }
+}
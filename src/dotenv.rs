@@ -0,0 +1,348 @@
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::definition::{AppError, AppResult};
+use crate::env::Env;
+use crate::subst;
+
+/// Parse a dotenv file: `KEY=VALUE` lines, optionally prefixed with `export`, and `#` comments.
+/// Single-quoted values are taken literally. Double-quoted values may span several lines,
+/// and understand `\n`, `\r`, `\t`, `\"`, `\\` and `\$`. Unquoted values end at a ` #` comment
+pub fn parse(content: &str) -> AppResult<Env> {
+    load(content, None)
+}
+
+/// Parse a dotenv file, expanding references such as `${NAME}` or `${NAME:-default}` in unquoted and double-quoted values.
+/// Records are expanded in order, and a reference sees the records above it and then `base`. References cannot form cycles this way,
+/// and `PATH=${PATH}:/opt/bin` extends the `PATH` defined above or in `base`. A reference to a variable which is not set
+/// and has no default is an error, and so is one to a variable first defined further down the file rather than a silent
+/// fallback to `base`. Messages refer to lines of `file`
+pub fn parse_expanded(content: &str, base: &Env, file: &str) -> AppResult<Env> {
+    load(content, Some((base, file)))
+}
+
+fn load(content: &str, expand: Option<(&Env, &str)>) -> AppResult<Env> {
+    let mut parser = Parser {
+        chars: content.chars().peekable(),
+        line: 1,
+        expand: expand.is_some(),
+    };
+    let mut records = Vec::new();
+    while let Some(record) = parser.record()? {
+        records.push(record);
+    }
+    // Line each variable is first defined on
+    let mut defined = HashMap::new();
+    for record in &records {
+        defined
+            .entry(record.key.as_bytes().to_vec())
+            .or_insert(record.line);
+    }
+
+    let mut env = Env(Vec::new());
+    let mut scope = expand.map(|(base, file)| (base.clone(), file));
+    for record in records {
+        let mut value = record.value.into_bytes();
+        if let Some((scope, file)) = &mut scope {
+            if record.expandable {
+                value = subst::substitute_in(&value, scope, file, record.line, &defined)?;
+            }
+            scope.set(record.key.as_bytes(), &value);
+        }
+        env.set(record.key.as_bytes(), &value);
+    }
+    Ok(env)
+}
+
+/// Quote `value` for dotenv files, in double quotes where `\`, `"`, `$` and line breaks are escaped
+pub fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '\\' | '"' | '$' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' => quoted.push_str(r"\n"),
+            '\r' => quoted.push_str(r"\r"),
+            '\t' => quoted.push_str(r"\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+    /// Whether values are to be expanded, so that `\$` in double quotes must become `$$`
+    expand: bool,
+}
+
+struct Record {
+    key: String,
+    value: String,
+    /// Line the record starts on
+    line: usize,
+    /// Whether the value was unquoted or double-quoted
+    expandable: bool,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> AppError {
+        AppError::InvalidDotenv(format!("line {}: {message}", self.line))
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next();
+        if c == Some('\n') {
+            self.line += 1;
+        }
+        c
+    }
+
+    fn skip_blanks(&mut self) {
+        while self
+            .chars
+            .next_if(|c| matches!(c, ' ' | '\t' | '\r'))
+            .is_some()
+        {}
+    }
+
+    fn skip_line(&mut self) {
+        while self.next().is_some_and(|c| c != '\n') {}
+    }
+
+    fn name(&mut self) -> String {
+        let mut name = String::new();
+        while let Some(c) = self
+            .chars
+            .next_if(|c| c.is_ascii_alphanumeric() || *c == '_')
+        {
+            name.push(c);
+        }
+        name
+    }
+
+    fn record(&mut self) -> AppResult<Option<Record>> {
+        loop {
+            self.skip_blanks();
+            match self.chars.peek() {
+                None => return Ok(None),
+                Some('\n' | '#') => self.skip_line(),
+                Some(_) => break,
+            }
+        }
+
+        let line = self.line;
+        let mut key = self.name();
+        if key == "export" && self.chars.peek().is_some_and(|c| *c == ' ' || *c == '\t') {
+            self.skip_blanks();
+            key = self.name();
+        }
+        if key.is_empty() || key.starts_with(|c: char| c.is_ascii_digit()) {
+            return Err(self.error("expected a variable name"));
+        }
+        self.skip_blanks();
+        if self.chars.next_if_eq(&'=').is_none() {
+            return Err(self.error(&format!("expected = after {key}")));
+        }
+        self.skip_blanks();
+
+        let (value, expandable) = match self.chars.peek() {
+            Some('\'') => (self.single_quoted()?, false),
+            Some('"') => (self.double_quoted()?, true),
+            _ => {
+                return Ok(Some(Record {
+                    key,
+                    value: self.unquoted(),
+                    line,
+                    expandable: true,
+                }));
+            }
+        };
+        self.skip_blanks();
+        match self.chars.peek() {
+            None | Some('\n' | '#') => self.skip_line(),
+            Some(_) => return Err(self.error(&format!("unexpected text after the value of {key}"))),
+        }
+        Ok(Some(Record {
+            key,
+            value,
+            line,
+            expandable,
+        }))
+    }
+
+    fn unquoted(&mut self) -> String {
+        let mut value = String::new();
+        while let Some(c) = self.next() {
+            match c {
+                '\n' => break,
+                '#' if value.ends_with([' ', '\t']) => {
+                    self.skip_line();
+                    break;
+                }
+                c => value.push(c),
+            }
+        }
+        value.trim_end().to_owned()
+    }
+
+    fn single_quoted(&mut self) -> AppResult<String> {
+        self.next();
+        let mut value = String::new();
+        loop {
+            match self.next() {
+                Some('\'') => return Ok(value),
+                Some(c) => value.push(c),
+                None => return Err(self.error("unterminated single quote")),
+            }
+        }
+    }
+
+    fn double_quoted(&mut self) -> AppResult<String> {
+        self.next();
+        let mut value = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(value),
+                Some('\\') => match self.next() {
+                    Some('n') => value.push('\n'),
+                    Some('r') => value.push('\r'),
+                    Some('t') => value.push('\t'),
+                    Some('$') if self.expand => value.push_str("$$"),
+                    Some(c @ ('"' | '\\' | '$')) => value.push(c),
+                    Some(c) => {
+                        value.push('\\');
+                        value.push(c);
+                    }
+                    None => return Err(self.error("unterminated double quote")),
+                },
+                Some(c) => value.push(c),
+                None => return Err(self.error("unterminated double quote")),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse, parse_expanded, quote};
+    use crate::definition::AppError;
+    use crate::env::Env;
+
+    #[test]
+    fn quoting() {
+        assert_eq!(quote("plain"), r#""plain""#);
+        assert_eq!(quote("a \"b\"\n$c\\"), r#""a \"b\"\n\$c\\""#);
+    }
+
+    #[test]
+    fn parsing() {
+        let content = r#"
+# comment
+export A=plain value  # trailing comment
+B='single $HOME \n'
+C="double \"quoted\"\n$x"
+D="spans
+two lines"
+E=
+F=url#fragment
+A=again
+"#;
+        assert_eq!(
+            parse(content).unwrap().to_strings(),
+            [
+                ("B", "single $HOME \\n"),
+                ("C", "double \"quoted\"\n$x"),
+                ("D", "spans\ntwo lines"),
+                ("E", ""),
+                ("F", "url#fragment"),
+                ("A", "again"),
+            ]
+            .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        );
+        assert_eq!(
+            parse(&format!("X={}", quote("round\n\"trip\" $x\\")))
+                .unwrap()
+                .to_strings(),
+            [("X".to_owned(), "round\n\"trip\" $x\\".to_owned())]
+        );
+    }
+
+    #[test]
+    fn expansion() {
+        let base = Env::from(Vec::from("PATH=/bin\0HOME=/home/user\0"));
+        let content = r#"
+PATH=${PATH}:/opt/bin
+DATA="$HOME/data"
+CACHE=${DATA}/cache
+LITERAL='${HOME}'
+ESCAPED="\${HOME} $$"
+PATH=${PATH}:/usr/local/bin
+"#;
+        assert_eq!(
+            parse_expanded(content, &base, "a.env")
+                .unwrap()
+                .to_strings(),
+            [
+                ("DATA", "/home/user/data"),
+                ("CACHE", "/home/user/data/cache"),
+                ("LITERAL", "${HOME}"),
+                ("ESCAPED", "${HOME} $"),
+                ("PATH", "/bin:/opt/bin:/usr/local/bin"),
+            ]
+            .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        );
+        assert_eq!(
+            parse_expanded("A=1\nB=${C:?is required}", &base, "a.env")
+                .unwrap_err()
+                .to_string(),
+            "a.env:2: C: is required"
+        );
+        assert_eq!(
+            parse_expanded("A=1\nP=abc$def${GHI}", &base, "a.env")
+                .unwrap_err()
+                .to_string(),
+            "a.env:2: def is not set, a.env:2: GHI is not set"
+        );
+        assert_eq!(
+            parse_expanded("P='abc$def'\nQ=abc${def-}", &base, "a.env")
+                .unwrap()
+                .to_strings(),
+            [("P", "abc$def"), ("Q", "abc")].map(|(key, value)| (key.to_owned(), value.to_owned()))
+        );
+        for content in [
+            "A=${B}\nB=${A}",
+            "A=${B-default}\nB=x",
+            "A=1\nHOME=$A/${PATH}\n\nPATH=x",
+        ] {
+            let err = parse_expanded(content, &base, "a.env").unwrap_err();
+            assert!(
+                matches!(err, AppError::InvalidDotenv(_)) && err.to_string().contains("below"),
+                "{content}: {err}"
+            );
+        }
+        assert!(
+            parse_expanded("A=${B}\nB=${A}", &base, "a.env")
+                .unwrap_err()
+                .to_string()
+                .ends_with("a.env:1: B is only defined below, on line 2")
+        );
+    }
+
+    #[test]
+    fn errors() {
+        for content in ["A", "1A=x", "A='open", "A=\"open", "A=\"x\" y"] {
+            assert!(
+                matches!(parse(content), Err(AppError::InvalidDotenv(_))),
+                "{content}"
+            );
+        }
+        assert!(parse("A=x\nB").unwrap_err().to_string().contains("line 2"));
+    }
+}
@@ -5,7 +5,7 @@ use serde::{Serialize, Serializer};
 use std::cmp::Ordering;
 use std::slice::Iter;
 
-#[derive(Eq, Debug)]
+#[derive(Eq, Debug, Clone)]
 pub struct RecordPair(pub Vec<u8>, pub Vec<u8>);
 
 impl PartialEq<Self> for RecordPair {
@@ -26,7 +26,7 @@ impl Ord for RecordPair {
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Env(pub Vec<RecordPair>);
 
 impl Serialize for Env {
@@ -44,14 +44,77 @@ impl Serialize for Env {
     }
 }
 
+/// Environment variables of one process, when several are printed together
+#[derive(Debug, Serialize)]
+pub struct ProcessEnv {
+    pub pid: u32,
+    pub comm: Option<String>,
+    pub ppid: Option<u32>,
+    pub uid: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cmdline: Option<String>,
+    #[serde(rename = "environment")]
+    pub env: Env,
+}
+
 impl Env {
     pub fn iter(&self) -> Iter<'_, RecordPair> {
         self.0.iter()
     }
 
+    /// Keep the variables named by `keys`, in their order and as often as they are given, like GNU printenv
     pub fn filter_keys(&mut self, keys: &[String]) {
+        self.0 = keys
+            .iter()
+            .flat_map(|key| self.0.iter().filter(|item| key.as_bytes() == item.0))
+            .cloned()
+            .collect();
+    }
+
+    /// Names among `keys` which are not set, each once
+    pub fn missing_keys<'a>(&self, keys: &'a [String]) -> Vec<&'a str> {
+        let mut missing: Vec<&str> = Vec::new();
+        for key in keys {
+            if !self.0.iter().any(|item| key.as_bytes() == item.0) && !missing.contains(&&**key) {
+                missing.push(key);
+            }
+        }
+        missing
+    }
+
+    /// Variables as text, for dotenv files and shell code
+    pub fn to_strings(&self) -> Vec<(String, String)> {
         self.0
-            .retain(|item| keys.iter().any(|key| key.as_bytes() == item.0));
+            .iter()
+            .map(|RecordPair(key, value)| {
+                (
+                    String::from_utf8_lossy(key).into_owned(),
+                    String::from_utf8_lossy(value).into_owned(),
+                )
+            })
+            .collect()
+    }
+
+    /// Value of `key`, the first one if it is set several times like `getenv()` does
+    pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        self.0
+            .iter()
+            .find(|item| item.0 == key)
+            .map(|item| &item.1[..])
+    }
+
+    /// Set `key` to `value`, dropping every previous value
+    pub fn set(&mut self, key: &[u8], value: &[u8]) {
+        self.unset(key);
+        self.0.push(RecordPair(key.to_vec(), value.to_vec()));
+    }
+
+    pub fn unset(&mut self, key: &[u8]) {
+        self.0.retain(|item| item.0 != key);
+    }
+
+    pub fn retain<F: FnMut(&RecordPair) -> bool>(&mut self, predicate: F) {
+        self.0.retain(predicate);
     }
 
     pub fn sort_by_key(&mut self, key_order: KeyOrder) {
@@ -70,13 +133,22 @@ impl Env {
 
 #[cfg(remote_env)]
 pub mod remote {
+    #[cfg(not(procfs))]
     use crate::AppResult;
-    pub fn get_environment_string(pid: u32) -> AppResult<Vec<u8>> {
-        #[cfg(target_os = "linux")]
-        {
-            crate::remote_linux_procfs::get_environment_string(pid)
-        }
 
+    /// How environments of other processes are read on this platform
+    #[cfg(target_os = "linux")]
+    pub const BACKEND: &str = "procfs";
+    #[cfg(unix_kvm)]
+    pub const BACKEND: &str = "kvm";
+    #[cfg(target_family = "windows")]
+    pub const BACKEND: &str = "process memory";
+    #[cfg(unix_apple_sysctl)]
+    pub const BACKEND: &str = "sysctl";
+
+    /// Procfs is read through `Source`, which knows which mount to use
+    #[cfg(not(procfs))]
+    pub fn get_environment_string(pid: u32) -> AppResult<Vec<u8>> {
         #[cfg(unix_kvm)]
         {
             crate::remote_unix_kvm::get_environment_string(pid)
@@ -98,6 +170,13 @@ pub mod remote {
         use crate::args::ColorMode;
         use crate::printer::Printer;
 
+        #[cfg(procfs)]
+        let actual = crate::remote_linux_procfs::get_environment_string(
+            &crate::procfs::Procfs::default(),
+            std::process::id(),
+        )
+        .unwrap();
+        #[cfg(not(procfs))]
         let actual = get_environment_string(std::process::id()).unwrap();
         let expected = super::Env::new();
         let printer = Printer {
@@ -200,4 +279,15 @@ mod test {
         assert_eq!(env.0.len(), 1);
         assert_eq!(env.0[0], RecordPair(Vec::from("C"), Vec::from("333")));
     }
+
+    #[test]
+    fn argument_order() {
+        let mut env = Env::from(Vec::from("A=111\0C=333\0B=222\0"));
+        let keys = ["B", "D", "A", "B", "D"].map(str::to_owned);
+        assert_eq!(env.missing_keys(&keys), ["D"]);
+
+        env.filter_keys(&keys);
+        let values: Vec<&[u8]> = env.iter().map(|record| &record.1[..]).collect();
+        assert_eq!(values, [b"222", b"111", b"222"]);
+    }
 }
@@ -0,0 +1,159 @@
+use std::ffi::OsString;
+use std::path::Path;
+use std::process::Command;
+
+use crate::args::ExecArgs;
+use crate::env::{Env, RecordPair};
+use crate::platform_ext::u8_vec_to_os_string;
+
+/// Exit statuses of GNU env, for failures before the command runs
+const FAILED: i32 = 125;
+const CANNOT_INVOKE: i32 = 126;
+const NOT_FOUND: i32 = 127;
+
+/// Parse `NAME=VALUE`, where the value may contain more `=`
+pub fn parse_assignment(word: &str) -> Result<(String, String), String> {
+    match word.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((name.to_owned(), value.to_owned())),
+        _ => Err(format!(
+            "expected NAME=VALUE, or the command after --: {word}"
+        )),
+    }
+}
+
+/// Arguments of `env [-i] [-0] [-u NAME] [-C DIR] [NAME=VALUE]... [COMMAND [ARG]...]`,
+/// rewritten into `printenv2 exec ... -- COMMAND [ARG]...`
+pub fn env_argv(argv: impl IntoIterator<Item = OsString>) -> Vec<OsString> {
+    let mut argv = argv.into_iter();
+    let mut rewritten = vec![argv.next().unwrap_or_default(), OsString::from("exec")];
+    let mut first = true;
+    while let Some(arg) = argv.next() {
+        let word = arg.to_string_lossy().into_owned();
+        if word == "--" {
+            rewritten.push(arg);
+            break;
+        }
+        // A lone `-` means -i
+        if first && word == "-" {
+            rewritten.push(OsString::from("-i"));
+        } else if matches!(word.as_str(), "--unset" | "--chdir") {
+            rewritten.push(arg);
+            rewritten.extend(argv.next());
+        } else if let Some(shorts) = word
+            .strip_prefix('-')
+            .filter(|shorts| !shorts.is_empty() && !shorts.starts_with('-'))
+        {
+            // Clusters such as `-iu NAME` and `-uNAME` are split, to know whether the next word is a value
+            for (at, short) in shorts.char_indices() {
+                rewritten.push(OsString::from(format!("-{short}")));
+                if matches!(short, 'u' | 'C') {
+                    let value = &shorts[at + short.len_utf8()..];
+                    if value.is_empty() {
+                        rewritten.extend(argv.next());
+                    } else {
+                        rewritten.push(OsString::from(value));
+                    }
+                    break;
+                }
+            }
+        } else if word.starts_with('-') || parse_assignment(&word).is_ok() {
+            rewritten.push(arg);
+        } else {
+            rewritten.push(OsString::from("--"));
+            rewritten.push(arg);
+            break;
+        }
+        first = false;
+    }
+    rewritten.extend(argv);
+    rewritten
+}
+
+/// Apply -i, -u and assignments to `env`
+pub fn prepare(env: &mut Env, exec_args: &ExecArgs) {
+    if exec_args.ignore_environment {
+        env.retain(|_| false);
+    }
+    for name in &exec_args.unset {
+        env.unset(name.as_bytes());
+    }
+    for (name, value) in &exec_args.assignments {
+        env.set(name.as_bytes(), value.as_bytes());
+    }
+}
+
+/// Run `command` with exactly `env`, and return the exit status to leave with.
+/// On Unix, printenv2 is replaced by the command and this only returns on failure.
+pub fn run(env: &Env, command: &[String], chdir: Option<&Path>) -> i32 {
+    let Some((program, arguments)) = command.split_first() else {
+        return FAILED;
+    };
+    if let Some(dir) = chdir
+        && let Err(err) = std::env::set_current_dir(dir)
+    {
+        eprintln!(
+            "printenv2: cannot change directory to {}: {err}",
+            dir.display()
+        );
+        return FAILED;
+    }
+
+    let mut child = Command::new(program);
+    child.args(arguments).env_clear().envs(
+        env.iter()
+            .map(|RecordPair(key, value)| (u8_vec_to_os_string(key), u8_vec_to_os_string(value))),
+    );
+
+    #[cfg(target_family = "unix")]
+    let err = std::os::unix::process::CommandExt::exec(&mut child);
+    #[cfg(not(target_family = "unix"))]
+    let err = match child.status() {
+        Ok(status) => return status.code().unwrap_or(FAILED),
+        Err(err) => err,
+    };
+
+    eprintln!("printenv2: cannot run {program}: {err}");
+    if err.kind() == std::io::ErrorKind::NotFound {
+        NOT_FOUND
+    } else {
+        CANNOT_INVOKE
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{env_argv, parse_assignment};
+    use std::ffi::OsString;
+
+    #[test]
+    fn assignments() {
+        assert_eq!(
+            parse_assignment("B=x=y"),
+            Ok(("B".to_owned(), "x=y".to_owned()))
+        );
+        assert!(parse_assignment("=weird").is_err());
+        assert!(parse_assignment("cmd").is_err());
+    }
+
+    #[test]
+    fn env_personality() {
+        let rewrite = |args: &[&str]| -> Vec<OsString> {
+            env_argv(args.iter().map(OsString::from))
+                .into_iter()
+                .skip(2)
+                .collect()
+        };
+        assert_eq!(
+            rewrite(&["env", "-", "-u", "X", "A=1", "cmd", "-x", "B=2"]),
+            ["-i", "-u", "X", "A=1", "--", "cmd", "-x", "B=2"]
+        );
+        assert_eq!(rewrite(&["env", "-0"]), ["-0"]);
+        assert_eq!(
+            rewrite(&["env", "-iu", "B", "-uC", "-0iCdir", "A=1", "cmd"]),
+            [
+                "-i", "-u", "B", "-u", "C", "-0", "-i", "-C", "dir", "A=1", "--", "cmd"
+            ]
+        );
+        assert_eq!(rewrite(&["env", "--", "cmd"]), ["--", "cmd"]);
+    }
+}
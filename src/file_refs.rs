@@ -0,0 +1,283 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::env::{Env, RecordPair};
+use crate::platform_ext::u8_vec_to_os_string;
+
+const SUFFIX: &[u8] = b"_FILE";
+const REDACTED: &[u8] = b"<redacted>";
+
+/// How `KEY_FILE=/path` variables are resolved into `KEY`
+#[derive(Debug, Clone)]
+pub struct Resolver {
+    /// Directories the files must be in, once symlinks are followed
+    pub dirs: Vec<PathBuf>,
+    pub max_size: u64,
+    /// Keep the `KEY_FILE` variables
+    pub keep: bool,
+    pub redact: bool,
+}
+
+impl Resolver {
+    /// Replace each `KEY_FILE` variable with `KEY` set to the content of the file, without one trailing newline.
+    /// Paths are looked up under `root`, the root directory of the process the variables are from, if it is not ours.
+    /// Variables which cannot be resolved are reported on stderr and left as they are
+    pub fn resolve(&self, env: &mut Env, root: Option<&Path>) {
+        let mut resolved = Vec::with_capacity(env.0.len());
+        for record in env.iter() {
+            let Some(name) = record
+                .0
+                .strip_suffix(SUFFIX)
+                .filter(|name| !name.is_empty())
+            else {
+                resolved.push(record.clone());
+                continue;
+            };
+            let result = if env.get(name).is_some() {
+                Err("the variable it would set is set already".to_owned())
+            } else {
+                self.read(&record.1, root)
+            };
+            match result {
+                Ok(value) => {
+                    if self.keep {
+                        resolved.push(record.clone());
+                    }
+                    let value = if self.redact {
+                        REDACTED.to_vec()
+                    } else {
+                        value
+                    };
+                    resolved.push(RecordPair(name.to_vec(), value));
+                }
+                Err(reason) => {
+                    eprintln!(
+                        "printenv2: cannot resolve {}: {reason}",
+                        String::from_utf8_lossy(&record.0)
+                    );
+                    resolved.push(record.clone());
+                }
+            }
+        }
+        env.0 = resolved;
+    }
+
+    fn read(&self, reference: &[u8], root: Option<&Path>) -> Result<Vec<u8>, String> {
+        let path = PathBuf::from(u8_vec_to_os_string(reference));
+        if !path.is_absolute() {
+            return Err(format!("{} is not an absolute path", path.display()));
+        }
+        let file = match root {
+            #[cfg(procfs)]
+            Some(root) => self.open_in(root, &path)?,
+            #[cfg(not(procfs))]
+            Some(_) => {
+                return Err("files of other processes cannot be read on this platform".to_owned());
+            }
+            None => self.open(&path)?,
+        };
+
+        let mut content = Vec::new();
+        file.take(self.max_size + 1)
+            .read_to_end(&mut content)
+            .map_err(|err| format!("{}: {err}", path.display()))?;
+        if content.len() as u64 > self.max_size {
+            return Err(format!(
+                "{} is larger than {} bytes",
+                path.display(),
+                self.max_size
+            ));
+        }
+        if content.ends_with(b"\n") {
+            content.pop();
+        }
+        Ok(content)
+    }
+
+    fn open(&self, path: &Path) -> Result<File, String> {
+        let real = path
+            .canonicalize()
+            .map_err(|err| format!("{}: {err}", path.display()))?;
+        if !self.is_allowed(&real) {
+            return Err(format!(
+                "{} is not in a directory allowed with --file-ref-dir",
+                real.display()
+            ));
+        }
+        File::open(&real).map_err(|err| format!("{}: {err}", path.display()))
+    }
+
+    /// Open `path` as the process whose root directory is `root` sees it. The allowed directory its path
+    /// starts with is looked up within `root`, and symlinks below it must not leave it
+    #[cfg(procfs)]
+    fn open_in(&self, root: &Path, path: &Path) -> Result<File, String> {
+        let outside = || {
+            format!(
+                "{} is not in a directory allowed with --file-ref-dir",
+                path.display()
+            )
+        };
+        let (dir, rest) = self
+            .dirs
+            .iter()
+            .find_map(|dir| Some((dir, path.strip_prefix(dir).ok()?)))
+            .ok_or_else(outside)?;
+        let open = || {
+            let root = File::open(root)?;
+            let dir = openat2(
+                &root,
+                dir,
+                libc::O_PATH | libc::O_DIRECTORY,
+                RESOLVE_IN_ROOT,
+            )?;
+            openat2(&dir, rest, libc::O_RDONLY, RESOLVE_BENEATH)
+        };
+        match open() {
+            Ok(file) => Ok(file),
+            Err(err) if err.raw_os_error() == Some(libc::EXDEV) => Err(outside()),
+            Err(err) => Err(format!("{}: {err}", path.display())),
+        }
+    }
+
+    fn is_allowed(&self, real: &Path) -> bool {
+        self.dirs
+            .iter()
+            .filter_map(|dir| dir.canonicalize().ok())
+            .any(|dir| real.starts_with(dir))
+    }
+}
+
+/// Flags of `struct open_how` for openat2(2)
+#[cfg(procfs)]
+const RESOLVE_BENEATH: u64 = 0x08;
+#[cfg(procfs)]
+const RESOLVE_IN_ROOT: u64 = 0x10;
+
+#[cfg(procfs)]
+#[repr(C)]
+struct OpenHow {
+    flags: u64,
+    mode: u64,
+    resolve: u64,
+}
+
+/// Open `path` relative to `dir`, resolved as `resolve` says
+#[cfg(procfs)]
+fn openat2(dir: &File, path: &Path, flags: i32, resolve: u64) -> std::io::Result<File> {
+    use std::os::fd::{AsRawFd, FromRawFd};
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+    let how = OpenHow {
+        flags: u64::try_from(flags | libc::O_CLOEXEC).unwrap_or_default(),
+        mode: 0,
+        resolve,
+    };
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_openat2,
+            dir.as_raw_fd(),
+            path.as_ptr(),
+            &raw const how,
+            std::mem::size_of::<OpenHow>(),
+        )
+    };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let fd = i32::try_from(fd).map_err(|_| std::io::Error::from_raw_os_error(libc::EBADF))?;
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+#[cfg(test)]
+mod test {
+    use super::Resolver;
+    use crate::env::Env;
+
+    #[test]
+    fn resolve() {
+        let dir = std::env::temp_dir().join(format!("printenv2-file-refs-{}", std::process::id()));
+        let secrets = dir.join("secrets");
+        std::fs::create_dir_all(&secrets).unwrap();
+        std::fs::write(secrets.join("password"), "hunter2\n\n").unwrap();
+        std::fs::write(secrets.join("large"), "x".repeat(100)).unwrap();
+        std::fs::write(dir.join("outside"), "no").unwrap();
+
+        let env = Env::from(
+            format!(
+                "DB_PASSWORD_FILE={0}/secrets/password\0BIG_FILE={0}/secrets/large\0\
+                 ESCAPE_FILE={0}/secrets/../outside\0USER=me\0USER_FILE={0}/secrets/password\0",
+                dir.display()
+            )
+            .into_bytes(),
+        );
+        let mut resolver = Resolver {
+            dirs: vec![secrets],
+            max_size: 64,
+            keep: false,
+            redact: false,
+        };
+        let resolve = |resolver: &Resolver| {
+            let mut env = env.clone();
+            resolver.resolve(&mut env, None);
+            env
+        };
+
+        let env_after = resolve(&resolver);
+        assert_eq!(env_after.get(b"DB_PASSWORD"), Some(&b"hunter2\n"[..]));
+        assert!(env_after.get(b"DB_PASSWORD_FILE").is_none());
+        for unresolved in ["BIG", "ESCAPE"] {
+            assert!(env_after.get(unresolved.as_bytes()).is_none());
+            assert!(
+                env_after
+                    .get(format!("{unresolved}_FILE").as_bytes())
+                    .is_some()
+            );
+        }
+        assert_eq!(env_after.get(b"USER"), Some(&b"me"[..]));
+
+        resolver.keep = true;
+        resolver.redact = true;
+        let env_after = resolve(&resolver);
+        assert_eq!(env_after.get(b"DB_PASSWORD"), Some(&b"<redacted>"[..]));
+        assert!(env_after.get(b"DB_PASSWORD_FILE").is_some());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(procfs)]
+    #[test]
+    fn resolve_in_root() {
+        let root =
+            std::env::temp_dir().join(format!("printenv2-file-refs-root-{}", std::process::id()));
+        let secrets = root.join("run/secrets");
+        std::fs::create_dir_all(&secrets).unwrap();
+        std::fs::write(secrets.join("password"), "in root\n").unwrap();
+        std::fs::write(root.join("outside"), "no").unwrap();
+        // Absolute paths are taken within the root, as the process does
+        std::os::unix::fs::symlink("/run", root.join("var-run")).unwrap();
+        std::os::unix::fs::symlink("password", secrets.join("link")).unwrap();
+        std::os::unix::fs::symlink("../../outside", secrets.join("escape")).unwrap();
+
+        let mut env = Env::from(
+            b"A_FILE=/var-run/secrets/password\0B_FILE=/run/secrets/link\0\
+              C_FILE=/run/secrets/escape\0D_FILE=/run/secrets/../outside\0E_FILE=/outside\0"
+                .to_vec(),
+        );
+        let resolver = Resolver {
+            dirs: vec!["/var-run/secrets".into(), "/run/secrets".into()],
+            max_size: 64,
+            keep: false,
+            redact: false,
+        };
+        resolver.resolve(&mut env, Some(&root));
+        assert_eq!(env.get(b"A"), Some(&b"in root"[..]));
+        assert_eq!(env.get(b"B"), Some(&b"in root"[..]));
+        for unresolved in ["C", "D", "E"] {
+            assert!(env.get(unresolved.as_bytes()).is_none());
+        }
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,48 @@
+use crate::definition::AppResult;
+use crate::env::RecordPair;
+use crate::pattern::Pattern;
+
+/// `KEY` or `KEY=VALUE` where both sides are patterns
+#[derive(Debug)]
+pub struct Query {
+    key: Pattern,
+    value: Option<Pattern>,
+}
+
+impl Query {
+    pub fn new(query: &str, regex: bool) -> AppResult<Self> {
+        let (key, value) = match query.split_once('=') {
+            Some((key, value)) => (key, Some(value)),
+            None => (query, None),
+        };
+        Ok(Self {
+            key: Pattern::new(key, regex)?,
+            value: value.map(|value| Pattern::new(value, regex)).transpose()?,
+        })
+    }
+
+    pub fn matches(&self, record: &RecordPair) -> bool {
+        self.key.matches(&record.0)
+            && self
+                .value
+                .as_ref()
+                .is_none_or(|value| value.matches(&record.1))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Query;
+    use crate::env::RecordPair;
+
+    #[test]
+    fn key_and_value() {
+        let record = RecordPair(b"AWS_PROFILE".to_vec(), b"old-account".to_vec());
+
+        assert!(Query::new("AWS_PROFILE", false).unwrap().matches(&record));
+        assert!(Query::new("AWS_*=old-*", false).unwrap().matches(&record));
+        assert!(!Query::new("AWS_*=new-*", false).unwrap().matches(&record));
+        assert!(Query::new("^AWS_=(", true).is_err());
+        assert!(Query::new("^AWS_=old", true).unwrap().matches(&record));
+    }
+}
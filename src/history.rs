@@ -0,0 +1,229 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::args::Shell;
+use crate::definition::AppResult;
+use crate::diff::EnvDiff;
+use crate::env::{Env, RecordPair};
+use crate::{hook, shell, snapshot, timestamp};
+
+/// Dumps of sessions which have not recorded anything for this long are removed
+const STALE: Duration = Duration::from_hours(30 * 24);
+
+// A session is the PID of the shell and the time the hook was loaded, since PIDs are reused.
+// It is kept in a shell variable which is not exported, so shells started from it have their own
+const BASH_HOOK: &str = r#"_printenv2_session=${_printenv2_session-$$-$(date +%s)}
+_printenv2_record() {
+  local status=$? command=
+  [[ $(HISTTIMEFORMAT= history 1) =~ ^[[:space:]]*[0-9]+[*[:space:]][[:space:]](.*)$ ]] && command=${BASH_REMATCH[1]}
+  EXE record --session "$_printenv2_session" --command="$command"
+  return $status
+}
+if [[ ";${PROMPT_COMMAND:-};" != *";_printenv2_record;"* ]]; then
+  PROMPT_COMMAND="${PROMPT_COMMAND:+$PROMPT_COMMAND;}_printenv2_record"
+fi
+"#;
+
+const ZSH_HOOK: &str = r#"_printenv2_session=${_printenv2_session-$$-$(date +%s)}
+_printenv2_record() {
+  EXE record --session "$_printenv2_session" --command="$(fc -ln -1 2>/dev/null)"
+}
+typeset -ag precmd_functions
+if (( ! ${precmd_functions[(I)_printenv2_record]} )); then
+  precmd_functions+=(_printenv2_record)
+fi
+"#;
+
+const FISH_HOOK: &str = r#"set -q _printenv2_session; or set -g _printenv2_session $fish_pid-(date +%s)
+function __printenv2_record --on-event fish_postexec
+    EXE record --session $_printenv2_session "--command=$argv[1]"
+end
+"#;
+
+/// What a command changed in the environment of a shell
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    pub time: String,
+    pub session: String,
+    pub cwd: String,
+    pub command: String,
+    pub changes: EnvDiff,
+}
+
+/// Session names end up in file names, so they are limited to letters, digits, `-` and `_`
+pub fn parse_session(session: &str) -> Result<String, String> {
+    if !session.is_empty()
+        && session
+            .bytes()
+            .all(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_')
+    {
+        Ok(session.to_owned())
+    } else {
+        Err(format!("expected letters, digits, - and _ only: {session}"))
+    }
+}
+
+/// Code to evaluate in the shell's startup file, which runs `record` after each command
+pub fn hook(shell: Shell) -> AppResult<String> {
+    let exe = shell::quote(&std::env::current_exe()?.to_string_lossy());
+    let template = match shell {
+        Shell::Bash => BASH_HOOK,
+        Shell::Zsh => ZSH_HOOK,
+        Shell::Fish => FISH_HOOK,
+    };
+    Ok(template.replace("EXE", &exe))
+}
+
+/// `$XDG_STATE_HOME/printenv2`, or `~/.local/state/printenv2`
+fn state_dir() -> AppResult<PathBuf> {
+    hook::user_dir("XDG_STATE_HOME", ".local/state")
+}
+
+/// Variables which change without anyone exporting them
+fn is_volatile(key: &[u8]) -> bool {
+    snapshot::VOLATILE
+        .iter()
+        .chain(&[hook::STATE])
+        .any(|volatile| volatile.as_bytes() == key)
+}
+
+fn dump(env: &Env) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for RecordPair(key, value) in env.iter() {
+        bytes.extend_from_slice(key);
+        bytes.push(b'=');
+        bytes.extend_from_slice(value);
+        bytes.push(0);
+    }
+    bytes
+}
+
+/// Compare the environment with the one seen at the previous prompt of `session`,
+/// and append the differences to the history, attributed to `command`
+pub fn record(session: &str, command: &str) -> AppResult<()> {
+    let dir = state_dir()?;
+    let sessions = dir.join("sessions");
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&sessions)?;
+
+    let mut current = Env::new();
+    current.retain(|record| !is_volatile(&record.0));
+    let path = sessions.join(session);
+    match fs::read(&path) {
+        Ok(previous) => {
+            let changes = EnvDiff::new(&Env::from(previous), &current);
+            if !changes.is_empty() {
+                let entry = Entry {
+                    time: timestamp::rfc3339(SystemTime::now()),
+                    session: session.to_owned(),
+                    cwd: std::env::current_dir()?.to_string_lossy().into_owned(),
+                    command: command.trim().to_owned(),
+                    changes,
+                };
+                let mut line = serde_json::to_vec(&entry)?;
+                line.push(b'\n');
+                private_file(&dir.join("history"), true)?.write_all(&line)?;
+            }
+        }
+        // The first prompt of a session only sets the baseline
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => remove_stale(&sessions),
+        Err(err) => return Err(err.into()),
+    }
+    private_file(&path, false)?.write_all(&dump(&current))?;
+    Ok(())
+}
+
+fn private_file(path: &Path, append: bool) -> std::io::Result<fs::File> {
+    OpenOptions::new()
+        .create(true)
+        .append(append)
+        .write(true)
+        .truncate(!append)
+        .mode(0o600)
+        .open(path)
+}
+
+fn remove_stale(sessions: &Path) {
+    let Ok(dumps) = fs::read_dir(sessions) else {
+        return;
+    };
+    for dump in dumps.flatten() {
+        let stale = dump
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .is_ok_and(|modified| modified.elapsed().is_ok_and(|elapsed| elapsed > STALE));
+        if stale {
+            let _ = fs::remove_file(dump.path());
+        }
+    }
+}
+
+/// Whether `entry` was recorded in `session`, which may also be the PID of the shell alone
+pub fn in_session(entry: &Entry, session: &str) -> bool {
+    entry
+        .session
+        .strip_prefix(session)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('-'))
+}
+
+/// Recorded entries, oldest first. Lines cut short by a crash are skipped
+pub fn load() -> AppResult<Vec<Entry>> {
+    let content = match fs::read_to_string(state_dir()?.join("history")) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+    Ok(parse(&content))
+}
+
+fn parse(content: &str) -> Vec<Entry> {
+    content
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Entry, in_session, parse, parse_session};
+    use crate::diff::EnvDiff;
+    use crate::env::Env;
+
+    #[test]
+    fn sessions() {
+        assert!(parse_session("1234").is_ok());
+        assert!(parse_session("work-2_b").is_ok());
+        assert!(parse_session("../x").is_err());
+        assert!(parse_session("").is_err());
+    }
+
+    #[test]
+    fn entries() {
+        let entry = Entry {
+            time: "2024-01-31T08:00:00Z".to_owned(),
+            session: "1234-1706688000".to_owned(),
+            cwd: "/home/user".to_owned(),
+            command: "export KUBECONFIG=~/dev".to_owned(),
+            changes: EnvDiff::new(
+                &Env::from(Vec::from("KUBECONFIG=/prod\0")),
+                &Env::from(Vec::from("KUBECONFIG=/dev\0")),
+            ),
+        };
+        let line = serde_json::to_string(&entry).unwrap();
+        let content = format!("{line}\n{{\"time\":\"2024-01-31T08:01\n{line}\n");
+        let entries = parse(&content);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0], entry);
+
+        assert!(in_session(&entry, "1234-1706688000"));
+        assert!(in_session(&entry, "1234"));
+        assert!(!in_session(&entry, "123"));
+        assert!(!in_session(&entry, "1234-1706"));
+    }
+}
@@ -0,0 +1,345 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::args::Shell;
+use crate::definition::{AppError, AppResult};
+use crate::env::Env;
+use crate::{dotenv, shell};
+
+/// Names of the files loaded, looked up in the working directory and its ancestors
+const FILES: [&str; 2] = [".env", ".envrc.env"];
+/// Variable holding what the hook applied, so the next prompt can revert it
+pub const STATE: &str = "PRINTENV2_HOOK";
+
+const BASH_HOOK: &str = r#"_printenv2_hook() {
+  local status=$?
+  eval "$(EXE export bash FLAGS)"
+  return $status
+}
+if [[ ";${PROMPT_COMMAND:-};" != *";_printenv2_hook;"* ]]; then
+  PROMPT_COMMAND="_printenv2_hook${PROMPT_COMMAND:+;$PROMPT_COMMAND}"
+fi
+"#;
+
+const ZSH_HOOK: &str = r#"_printenv2_hook() {
+  eval "$(EXE export zsh FLAGS)"
+}
+typeset -ag precmd_functions chpwd_functions
+if (( ! ${precmd_functions[(I)_printenv2_hook]} )); then
+  precmd_functions=(_printenv2_hook $precmd_functions)
+fi
+if (( ! ${chpwd_functions[(I)_printenv2_hook]} )); then
+  chpwd_functions=(_printenv2_hook $chpwd_functions)
+fi
+"#;
+
+const FISH_HOOK: &str = r"function __printenv2_hook --on-event fish_prompt
+    EXE export fish FLAGS | source
+end
+";
+
+/// What the hook applied: the file, the values it set, and the values they replaced
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct State {
+    file: PathBuf,
+    loaded: BTreeMap<String, String>,
+    /// `None` for variables which were not set
+    previous: BTreeMap<String, Option<String>>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Change {
+    Set(String, String),
+    Unset(String),
+}
+
+/// Code to evaluate in the shell's startup file, which runs `export` before each prompt, expanding values or not
+pub fn hook(shell: Shell, expand: bool) -> AppResult<String> {
+    let exe = shell::quote(&std::env::current_exe()?.to_string_lossy());
+    let template = match shell {
+        Shell::Bash => BASH_HOOK,
+        Shell::Zsh => ZSH_HOOK,
+        Shell::Fish => FISH_HOOK,
+    };
+    let flags = if expand { "--expand" } else { "--no-expand" };
+    Ok(template.replace("EXE", &exe).replace("FLAGS", flags))
+}
+
+/// Shell code which reverts what was applied for the previous directory, and applies the nearest allowed file.
+/// With `expand`, references in the file see the shell's variables as they are once the previous file is reverted
+pub fn export(shell: Shell, expand: bool) -> AppResult<String> {
+    let state = std::env::var(STATE)
+        .ok()
+        .and_then(|state| serde_json::from_str::<State>(&state).ok());
+    let env = Env::new();
+
+    let mut target = None;
+    if let Some(path) = nearest(&std::env::current_dir()?) {
+        if is_allowed(&path)? {
+            let content = std::fs::read_to_string(&path)?;
+            let loaded = if expand {
+                let base = unloaded(&env, state.as_ref());
+                dotenv::parse_expanded(&content, &base, &path.display().to_string())
+            } else {
+                dotenv::parse(&content)
+            };
+            let loaded =
+                loaded.map_err(|err| err.context(format!("cannot load {}", path.display())))?;
+            target = Some((path, loaded.to_strings().into_iter().collect()));
+        } else {
+            eprintln!(
+                "printenv2: {} is not allowed, run `printenv2 allow` to load it",
+                path.display()
+            );
+        }
+    }
+
+    let Some(changes) = transition(&env, state.as_ref(), target) else {
+        return Ok(String::new());
+    };
+    Ok(changes
+        .iter()
+        .map(|change| match change {
+            Change::Set(key, value) => shell::set(shell, key, value),
+            Change::Unset(key) => shell::unset(shell, key),
+        })
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+/// `env` with the values replaced by `state` restored, so that `PATH=${PATH}:/opt/bin` does not grow on every prompt
+fn unloaded(env: &Env, state: Option<&State>) -> Env {
+    let mut env = env.clone();
+    for (key, value) in state.iter().flat_map(|state| &state.previous) {
+        match value {
+            Some(value) => env.set(key.as_bytes(), value.as_bytes()),
+            None => env.unset(key.as_bytes()),
+        }
+    }
+    env
+}
+
+/// Changes from what `state` applied to the variables of `target`, or `None` when there is nothing to do
+fn transition(
+    env: &Env,
+    state: Option<&State>,
+    target: Option<(PathBuf, BTreeMap<String, String>)>,
+) -> Option<Vec<Change>> {
+    if let (Some(state), Some((file, loaded))) = (state, &target)
+        && state.file == *file
+        && state.loaded == *loaded
+    {
+        return None;
+    }
+    if state.is_none() && target.is_none() {
+        return None;
+    }
+
+    let mut changes = Vec::new();
+    // Values as they are once the previous file is unloaded
+    let mut reverted: BTreeMap<&str, Option<String>> = BTreeMap::new();
+    if let Some(state) = state {
+        eprintln!("printenv2: unloading {}", state.file.display());
+        for (key, value) in &state.previous {
+            changes.push(value.as_ref().map_or_else(
+                || Change::Unset(key.clone()),
+                |value| Change::Set(key.clone(), value.clone()),
+            ));
+            reverted.insert(key, value.clone());
+        }
+    }
+
+    let Some((file, loaded)) = target else {
+        changes.push(Change::Unset(STATE.to_owned()));
+        return Some(changes);
+    };
+    eprintln!("printenv2: loading {}", file.display());
+    let previous = loaded
+        .keys()
+        .map(|key| {
+            let value = reverted.get(key.as_str()).cloned().unwrap_or_else(|| {
+                env.get(key.as_bytes())
+                    .map(|value| String::from_utf8_lossy(value).into_owned())
+            });
+            (key.clone(), value)
+        })
+        .collect();
+    for (key, value) in &loaded {
+        changes.push(Change::Set(key.clone(), value.clone()));
+    }
+    let state = State {
+        file,
+        loaded,
+        previous,
+    };
+    changes.push(Change::Set(
+        STATE.to_owned(),
+        serde_json::to_string(&state).unwrap_or_default(),
+    ));
+    Some(changes)
+}
+
+/// The file to load for `dir`, in it or in the closest of its ancestors
+pub fn nearest(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .flat_map(|dir| FILES.map(|name| dir.join(name)))
+        .find(|path| path.is_file())
+}
+
+/// `printenv2` in the XDG base directory named by `variable`, or in `fallback` under the home directory
+pub fn user_dir(variable: &str, fallback: &str) -> AppResult<PathBuf> {
+    let base = std::env::var_os(variable)
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(fallback)))
+        .ok_or_else(|| {
+            AppError::StdIo(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("neither {variable} nor HOME is set"),
+            ))
+        })?;
+    Ok(base.join("printenv2"))
+}
+
+/// `$XDG_CONFIG_HOME/printenv2/allow`, or `~/.config/printenv2/allow`
+fn allow_dir() -> AppResult<PathBuf> {
+    Ok(user_dir("XDG_CONFIG_HOME", ".config")?.join("allow"))
+}
+
+/// Entry of `path` in the allow-list, holding its path and the content which was allowed,
+/// so that a file edited since then is not loaded. Entries are named after an FNV-1a hash of the path
+fn allow_entry(path: &Path) -> AppResult<PathBuf> {
+    let hash = path
+        .as_os_str()
+        .as_encoded_bytes()
+        .iter()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+    Ok(allow_dir()?.join(format!("{hash:016x}")))
+}
+
+fn entry_content(path: &Path, content: &[u8]) -> Vec<u8> {
+    let mut entry = path.as_os_str().as_encoded_bytes().to_vec();
+    entry.push(0);
+    entry.extend_from_slice(content);
+    entry
+}
+
+fn is_allowed(path: &Path) -> AppResult<bool> {
+    let path = path.canonicalize()?;
+    let Ok(entry) = std::fs::read(allow_entry(&path)?) else {
+        return Ok(false);
+    };
+    Ok(entry == entry_content(&path, &std::fs::read(&path)?))
+}
+
+/// Trust `path` as it is now, or stop trusting it
+pub fn allow(path: &Path, revoke: bool) -> AppResult<()> {
+    let path = path.canonicalize()?;
+    let entry = allow_entry(&path)?;
+    if revoke {
+        match std::fs::remove_file(&entry) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+            _ => eprintln!("printenv2: revoked {}", path.display()),
+        }
+        return Ok(());
+    }
+    std::fs::create_dir_all(allow_dir()?)?;
+    std::fs::write(entry, entry_content(&path, &std::fs::read(&path)?))?;
+    eprintln!("printenv2: allowed {}", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Change, STATE, State, transition, unloaded};
+    use crate::dotenv;
+    use crate::env::Env;
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+
+    fn loaded(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| ((*key).to_owned(), (*value).to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn load_switch_unload() {
+        let env = Env::from(Vec::from("A=shell\0"));
+        let file = PathBuf::from("/p/.env");
+
+        let changes = transition(
+            &env,
+            None,
+            Some((file.clone(), loaded(&[("A", "1"), ("B", "2")]))),
+        )
+        .unwrap();
+        assert_eq!(
+            changes[..2],
+            [
+                Change::Set("A".to_owned(), "1".to_owned()),
+                Change::Set("B".to_owned(), "2".to_owned())
+            ]
+        );
+        let Some(Change::Set(key, state)) = changes.last() else {
+            panic!("expected the state to be saved");
+        };
+        assert_eq!(key, STATE);
+        let state: State = serde_json::from_str(state).unwrap();
+        assert_eq!(state.previous.get("A"), Some(&Some("shell".to_owned())));
+        assert_eq!(state.previous.get("B"), Some(&None));
+
+        // Nothing to do while the file stays the same
+        assert!(
+            transition(
+                &env,
+                Some(&state),
+                Some((file, loaded(&[("A", "1"), ("B", "2")])))
+            )
+            .is_none()
+        );
+
+        let changes = transition(
+            &env,
+            Some(&state),
+            Some((PathBuf::from("/q/.env"), loaded(&[("B", "3")]))),
+        )
+        .unwrap();
+        assert!(changes.contains(&Change::Unset("B".to_owned())));
+        assert!(changes.contains(&Change::Set("A".to_owned(), "shell".to_owned())));
+        assert!(changes.contains(&Change::Set("B".to_owned(), "3".to_owned())));
+
+        let changes = transition(&env, Some(&state), None).unwrap();
+        assert_eq!(changes.last(), Some(&Change::Unset(STATE.to_owned())));
+        assert!(transition(&env, None, None).is_none());
+    }
+
+    #[test]
+    fn expand_on_unloaded() {
+        let file = PathBuf::from("/p/.env");
+        let content = "PATH=${PATH}:/opt/bin\nNEW=${NEW-fresh}";
+        let env = Env::from(Vec::from("PATH=/usr/bin\0"));
+        let target = |env: &Env, state: Option<&State>| {
+            let loaded = dotenv::parse_expanded(content, &unloaded(env, state), "/p/.env").unwrap();
+            Some((file.clone(), loaded.to_strings().into_iter().collect()))
+        };
+
+        let changes = transition(&env, None, target(&env, None)).unwrap();
+        assert!(changes.contains(&Change::Set(
+            "PATH".to_owned(),
+            "/usr/bin:/opt/bin".to_owned()
+        )));
+        let Some(Change::Set(_, state)) = changes.last() else {
+            panic!("expected the state to be saved");
+        };
+        let state: State = serde_json::from_str(state).unwrap();
+
+        // At the next prompt, the shell has the loaded values, which the file must not extend again
+        let env = Env::from(Vec::from("PATH=/usr/bin:/opt/bin\0NEW=fresh\0"));
+        assert!(transition(&env, Some(&state), target(&env, Some(&state))).is_none());
+    }
+}
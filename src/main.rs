@@ -5,15 +5,40 @@
 #![deny(clippy::cargo)]
 #![allow(clippy::multiple_crate_versions)]
 
-use std::ffi::OsString;
-use std::fs::File;
-use std::io::{Read, Stdout, Write};
+use std::io::{Read, Write};
 
+#[cfg(target_family = "unix")]
+mod agent;
 mod args;
+#[cfg(procfs)]
+mod blame;
+#[cfg(target_family = "unix")]
+mod capture;
+#[cfg(procfs)]
+mod context;
+mod coredump;
 mod definition;
+mod diff;
+#[cfg(procfs)]
+mod doctor;
+mod dotenv;
 mod env;
+mod exec;
+mod file_refs;
+#[cfg(procfs)]
+mod find;
+#[cfg(target_family = "unix")]
+mod history;
+#[cfg(target_family = "unix")]
+mod hook;
+mod matrix;
+mod pattern;
+#[cfg(procfs)]
+mod pidfd;
 mod platform_ext;
 mod printer;
+#[cfg(procfs)]
+mod procfs;
 #[cfg(unix_apple_sysctl)]
 mod remote_apple_sysctl;
 #[cfg(debugger_helper)]
@@ -24,13 +49,48 @@ mod remote_linux_procfs;
 mod remote_unix_kvm;
 #[cfg(all(remote_env, target_family = "windows"))]
 mod remote_windows;
+#[cfg(procfs)]
+mod scan;
+#[cfg(procfs)]
+mod selector;
+#[cfg(target_family = "unix")]
+mod shell;
+mod snapshot;
+mod source;
+mod subst;
+mod suggest;
+mod timestamp;
+mod watch;
 
 use definition::AppResult;
 use printer::Printer;
+use source::Source;
 
-fn main() -> AppResult<()> {
+fn main() {
     let args = args::parse();
+    if let Err(err) = run(&args) {
+        let report = create_printer(&args).print_error(&err, &hints(&args, &err));
+        let _ = std::io::stderr().write_all(&report);
+        std::process::exit(err.kind().exit_code());
+    }
+}
+
+/// Findings of the doctor about the process, or else a generic suggestion
+fn hints(args: &args::Args, err: &definition::AppError) -> Vec<String> {
+    // With --pid-ns container, the doctor looks at the PID the given one was translated into
+    #[cfg(procfs)]
+    if let Some(pid) = args.pid.first().and_then(|pid| host_pid(args, *pid).ok()) {
+        let hints = doctor::hints(&procfs::Procfs::new(&args.proc_root), pid, err);
+        if !hints.is_empty() {
+            return hints;
+        }
+    }
+    #[cfg(not(procfs))]
+    let _ = args;
+    err.hint().into_iter().collect()
+}
 
+fn run(args: &args::Args) -> AppResult<()> {
     #[cfg(debugger_helper)]
     if args.debugger_helper == Some(args::DebuggerHelper::Gdb) {
         colored::control::set_override(false);
@@ -38,45 +98,609 @@ fn main() -> AppResult<()> {
         return Ok(());
     }
 
+    let mut sources = initial_sources(args)?;
+
+    let printer = create_printer(args);
+    let options = read_options(args);
+
+    if let Some(args::Command::Diff(diff_args)) = &args.command {
+        return run_diff(diff_args, args, &options, printer);
+    }
+
+    #[cfg(procfs)]
+    if let Some(args::Command::Serve(serve_args)) = &args.command {
+        return agent::serve(serve_args, args.regex, &options);
+    }
+
+    #[cfg(target_family = "unix")]
+    if let Some(args::Command::Capture(capture_args)) = &args.command {
+        return run_capture(capture_args, args, printer);
+    }
+
+    #[cfg(target_family = "unix")]
+    if run_hook_commands(args)? {
+        return Ok(());
+    }
+
+    #[cfg(target_family = "unix")]
+    if let Some(args::Command::History(history_args)) = &args.command {
+        return run_history(history_args, args, &printer);
+    }
+
+    #[cfg(procfs)]
+    if let Some(args::Command::Doctor(doctor_args)) = &args.command {
+        return run_doctor(doctor_args, args, &options.procfs, &printer);
+    }
+
+    #[cfg(procfs)]
+    if run_procfs_modes(args, &options.procfs, &printer, &mut sources)? {
+        return Ok(());
+    }
+
+    let source = sources.first().unwrap_or(&Source::Current);
+    match &args.command {
+        Some(args::Command::Exec(exec_args)) => {
+            return run_exec(exec_args, source, &options, &printer);
+        }
+        Some(args::Command::Subst(subst_args)) => {
+            return run_subst(subst_args, args, source, &options);
+        }
+        _ => (),
+    }
+
+    if args.watch {
+        return watch::watch(source, &options, args.interval, &args.variables, &printer);
+    }
+
+    if args.save_snapshot.is_some() || args.against_snapshot.is_some() {
+        return run_snapshot(args, source, &options, &printer);
+    }
+
+    if args.matrix {
+        let columns = sources
+            .iter()
+            .map(|source| {
+                let mut env = source.read(&options)?;
+                refine(&mut env, args);
+                Ok((source.label(), env))
+            })
+            .collect::<AppResult<Vec<_>>>()?;
+        let mut matrix = matrix::Matrix::new(&columns);
+        matrix.sort_by_key(args.key_order);
+        if args.differences_only {
+            matrix.retain_differences();
+        }
+        let output = printer.print_matrix(&matrix)?;
+        std::io::stdout().write_all(&output)?;
+        return Ok(());
+    }
+
+    let mut env = source.read(&options)?;
+    let missing = env.missing_keys(&args.variables);
+    if args.report_missing {
+        for name in &missing {
+            report_missing(&env, name);
+        }
+    }
+    let missing = !missing.is_empty();
+    refine(&mut env, args);
+
+    #[cfg(procfs)]
+    let output = match sources.first() {
+        Some(Source::Pid(pid) | Source::Process(procfs::ProcessId { pid, .. })) if args.context => {
+            let context = context::Context::new(&options.procfs, *pid)?;
+            printer.print_context(&context, &env)?
+        }
+        _ => printer.print(&env)?,
+    };
+    #[cfg(not(procfs))]
+    let output = printer.print(&env)?;
+    std::io::stdout().write_all(&output)?;
+
+    if missing {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Read a source to print, with `_FILE` variables resolved when --resolve-file-refs is given
+/// How sources are read, as given on the command line
+fn read_options(args: &args::Args) -> source::ReadOptions {
+    source::ReadOptions {
+        format: args.load_format,
+        expand: !args.no_expand,
+        #[cfg(procfs)]
+        procfs: procfs::Procfs::new(&args.proc_root),
+        file_refs: args.resolve_file_refs.then(|| file_refs::Resolver {
+            dirs: args.file_ref_dir.clone(),
+            max_size: args.file_ref_max_size,
+            keep: args.keep_file_refs,
+            redact: args.redact_file_refs,
+        }),
+    }
+}
+
+fn report_missing(env: &env::Env, name: &str) {
+    match suggest::closest(name, env.iter().map(|record| &record.0[..])) {
+        Some(similar) => eprintln!(
+            "printenv2: {name} is not set; did you mean {}?",
+            String::from_utf8_lossy(similar)
+        ),
+        None => eprintln!("printenv2: {name} is not set"),
+    }
+}
+
+/// Sources given with --pid, --load, --core, --via and --remote
+fn initial_sources(args: &args::Args) -> AppResult<Vec<Source>> {
     #[cfg(remote_env)]
-    let pid = args.pid;
+    let pids = args.pid.clone();
+    #[cfg(not(remote_env))]
+    let pids: Vec<u32> = Vec::new();
 
+    #[cfg(target_family = "unix")]
+    if let Some(endpoint) = &args.remote {
+        let token = match &args.token_file {
+            Some(path) => Some(std::fs::read_to_string(path)?.trim().to_owned()),
+            None => None,
+        };
+        #[cfg(procfs)]
+        let name = args.name.as_ref().map(|name| format!("name:{name}"));
+        #[cfg(not(procfs))]
+        let name = None;
+        let target = pids
+            .first()
+            .map(|pid| format!("pid:{pid}"))
+            .or(name)
+            .unwrap_or_else(|| "self".to_owned());
+        return Ok(vec![Source::Remote {
+            endpoint: endpoint.clone(),
+            token,
+            target,
+        }]);
+    }
+
+    if let Some(prefix) = &args.via {
+        let via = |pid| Source::Via {
+            prefix: prefix.clone(),
+            pid,
+        };
+        if pids.is_empty() {
+            return Ok(vec![via(None)]);
+        }
+        return Ok(pids.into_iter().map(|pid| via(Some(pid))).collect());
+    }
+
+    #[cfg(procfs)]
+    let mut sources: Vec<Source> = pids
+        .into_iter()
+        .map(|pid| Ok(Source::Pid(host_pid(args, pid)?)))
+        .collect::<AppResult<_>>()?;
+    #[cfg(all(remote_env, not(procfs)))]
+    let mut sources: Vec<Source> = pids.into_iter().map(Source::Pid).collect();
     #[cfg(not(remote_env))]
-    let pid: Option<u32> = None;
-
-    let env = {
-        let mut env = match (args.load, pid) {
-            (Some(path), None) => {
-                let mut content = Vec::new();
-                if path == OsString::from("-") {
-                    let stdin = std::io::stdin();
-                    let mut reader = stdin.lock();
-                    reader.read_to_end(&mut content)?;
-                } else {
-                    let mut file = File::open(path)?;
-                    file.read_to_end(&mut content)?;
+    let mut sources: Vec<Source> = Vec::new();
+    sources.extend(args.load.iter().cloned().map(Source::File));
+    sources.extend(args.core.iter().cloned().map(Source::Core));
+    Ok(sources)
+}
+
+/// Handle process selection which relies on procfs. Returns whether output has been printed already
+#[cfg(procfs)]
+fn run_procfs_modes(
+    args: &args::Args,
+    procfs: &procfs::Procfs,
+    printer: &Printer,
+    sources: &mut Vec<Source>,
+) -> AppResult<bool> {
+    let query = args
+        .find
+        .as_deref()
+        .map(|query| find::Query::new(query, args.regex))
+        .transpose()?;
+
+    let group = if args.all || query.is_some() {
+        Some(scan::all_processes(procfs)?)
+    } else if let Some(root) = args.tree {
+        Some(scan::process_tree(procfs, host_pid(args, root)?)?)
+    } else if let Some(selector) = args
+        .selectors()
+        .into_iter()
+        .next()
+        // --remote resolves --name on the agent
+        .filter(|_| args.remote.is_none())
+    {
+        let selector = match selector {
+            selector::Selector::PpidOf(pid) => selector::Selector::PpidOf(host_pid(args, pid)?),
+            selector => selector,
+        };
+        let processes = selector.resolve(procfs)?;
+        if args.matrix {
+            sources.extend(processes.into_iter().map(Source::Process));
+            None
+        } else if args.all_matches {
+            Some(processes)
+        } else if let [only] = processes[..] {
+            sources.push(Source::Process(only));
+            None
+        } else {
+            return Err(definition::AppError::AmbiguousProcess(
+                processes
+                    .into_iter()
+                    .map(|process| {
+                        let comm = procfs
+                            .stat(process.pid)
+                            .map(|stat| stat.comm)
+                            .unwrap_or_default();
+                        (process.pid, comm)
+                    })
+                    .collect(),
+            ));
+        }
+    } else {
+        None
+    };
+
+    if let Some(group) = group {
+        print_process_group(procfs, &group, query.as_ref(), printer, args)?;
+        return Ok(true);
+    }
+
+    if args.blame {
+        let pid = match sources.first() {
+            Some(Source::Pid(pid) | Source::Process(procfs::ProcessId { pid, .. })) => *pid,
+            _ => procfs.self_pid(),
+        };
+        let chain = blame::blame(procfs, pid, &args.variables)?;
+        let output = printer.print_blame(&chain)?;
+        std::io::stdout().write_all(&output)?;
+        return Ok(true);
+    }
+
+    Ok(false)
+}
+
+/// Translate a PID given on the command line into a PID of the procfs root
+#[cfg(procfs)]
+fn host_pid(args: &args::Args, pid: u32) -> AppResult<u32> {
+    match args.pid_ns {
+        args::PidNamespace::Host => Ok(pid),
+        args::PidNamespace::Container => procfs::Procfs::new(&args.proc_root).host_pid(pid),
+    }
+}
+
+#[cfg(procfs)]
+fn run_doctor(
+    doctor_args: &args::DoctorArgs,
+    args: &args::Args,
+    procfs: &procfs::Procfs,
+    printer: &Printer,
+) -> AppResult<()> {
+    let mut findings = doctor::backends(procfs);
+    if let Some(pid) = doctor_args.pid {
+        findings.extend(doctor::diagnose(procfs, host_pid(args, pid)?));
+    }
+    let output = printer.print_findings(&findings)?;
+    std::io::stdout().write_all(&output)?;
+
+    if doctor_args.pid.is_some()
+        && findings
+            .iter()
+            .skip_while(|finding| finding.check != "exists")
+            .any(|finding| finding.status == doctor::Status::Blocked)
+    {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+#[cfg(procfs)]
+fn print_process_group(
+    procfs: &procfs::Procfs,
+    group: &[procfs::ProcessId],
+    query: Option<&find::Query>,
+    printer: &Printer,
+    args: &args::Args,
+) -> AppResult<()> {
+    let euid = unsafe { libc::geteuid() };
+    let mut processes = Vec::new();
+    let mut failed = false;
+    let mut others = 0;
+    for (pid, result) in scan::read_processes(procfs, group) {
+        match (result, query) {
+            (Ok(mut process), Some(query)) => {
+                process.env.retain(|record| query.matches(record));
+                if !process.env.0.is_empty() {
+                    refine(&mut process.env, args);
+                    process.cmdline = procfs.cmdline(pid).ok();
+                    processes.push(process);
                 }
-                env::Env::from(content)
             }
-            #[cfg(remote_env)]
-            (None, Some(pid)) => env::Env::from(env::remote::get_environment_string(pid)?),
-            (None, None) => env::Env::new(),
-            _ => unreachable!(),
-        };
+            (Ok(mut process), None) => {
+                refine(&mut process.env, args);
+                processes.push(process);
+            }
+            // Searching only covers processes that can be read
+            (Err(_), Some(_)) => (),
+            // Processes which exited meanwhile are no longer part of the group
+            (Err(err), None) if err.kind() == definition::ErrorKind::NotFound => (),
+            // Without root, processes of other users are expected to be unreadable
+            (Err(err), None)
+                if err.kind() == definition::ErrorKind::PermissionDenied
+                    && euid != 0
+                    && procfs.status(pid).is_ok_and(|status| status.uid != euid) =>
+            {
+                others += 1;
+            }
+            (Err(err), None) => {
+                eprintln!("printenv2: skipped {pid}: {err}");
+                failed = true;
+            }
+        }
+    }
+
+    let output = printer.print_processes(&processes)?;
+    std::io::stdout().write_all(&output)?;
+    if others > 0 {
+        eprintln!("printenv2: skipped {others} processes of other users, which require root");
+    }
+    if failed || (processes.is_empty() && others > 0) {
+        std::process::exit(definition::ErrorKind::PartialOutput.exit_code());
+    }
+    if processes.is_empty() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn run_exec(
+    exec_args: &args::ExecArgs,
+    source: &Source,
+    options: &source::ReadOptions,
+    printer: &Printer,
+) -> AppResult<()> {
+    let mut env = source.read(options)?;
+    exec::prepare(&mut env, exec_args);
+    if exec_args.command.is_empty() {
+        let output = printer.print(&env)?;
+        std::io::stdout().write_all(&output)?;
+        return Ok(());
+    }
+    std::process::exit(exec::run(
+        &env,
+        &exec_args.command,
+        exec_args.chdir.as_deref(),
+    ));
+}
+
+fn run_subst(
+    subst_args: &args::SubstArgs,
+    args: &args::Args,
+    source: &Source,
+    options: &source::ReadOptions,
+) -> AppResult<()> {
+    let only = subst_args
+        .only
+        .iter()
+        .map(|pattern| pattern::Pattern::new(pattern, args.regex))
+        .collect::<AppResult<Vec<_>>>()?;
+    let env = source.read(options)?;
+    let template = if let Some(path) = &subst_args.template {
+        std::fs::read(path).map_err(|err| {
+            definition::AppError::from(err).context(format!("cannot read {}", path.display()))
+        })?
+    } else {
+        let mut template = Vec::new();
+        std::io::stdin().read_to_end(&mut template)?;
+        template
+    };
+    // Nothing is written unless the whole template could be substituted
+    let output = subst::substitute(&template, &env, &only, subst_args.strict)?;
+    std::io::stdout().write_all(&output)?;
+    Ok(())
+}
+
+/// Subcommands of the shell hook. Returns whether one was run
+#[cfg(target_family = "unix")]
+fn run_hook_commands(args: &args::Args) -> AppResult<bool> {
+    let code = match &args.command {
+        Some(args::Command::Hook(shell_args)) => hook::hook(shell_args.shell, !args.no_expand)?,
+        Some(args::Command::Export(shell_args)) => hook::export(shell_args.shell, !args.no_expand)?,
+        Some(args::Command::Allow(allow_args)) => {
+            let file = match &allow_args.file {
+                Some(file) => file.clone(),
+                None => hook::nearest(&std::env::current_dir()?).ok_or_else(|| {
+                    definition::AppError::StdIo(std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        "no .env or .envrc.env file in this directory or its parents",
+                    ))
+                })?,
+            };
+            hook::allow(&file, allow_args.revoke)?;
+            return Ok(true);
+        }
+        Some(args::Command::History(args::HistoryArgs {
+            hook: Some(shell), ..
+        })) => history::hook(*shell)?,
+        Some(args::Command::Record(record_args)) => {
+            history::record(&record_args.session, &record_args.command)?;
+            return Ok(true);
+        }
+        _ => return Ok(false),
+    };
+    println!("{code}");
+    Ok(true)
+}
+
+#[cfg(target_family = "unix")]
+fn run_history(
+    history_args: &args::HistoryArgs,
+    args: &args::Args,
+    printer: &Printer,
+) -> AppResult<()> {
+    let patterns = history_args
+        .variables
+        .iter()
+        .map(|pattern| pattern::Pattern::new(pattern, args.regex))
+        .collect::<AppResult<Vec<_>>>()?;
+    let mut entries = history::load()?;
+    entries.retain_mut(|entry| {
+        if !patterns.is_empty() {
+            entry
+                .changes
+                .retain_keys(|key| patterns.iter().any(|pattern| pattern.matches(key)));
+        }
+        history_args
+            .session
+            .as_ref()
+            .is_none_or(|session| history::in_session(entry, session))
+            && !entry.changes.is_empty()
+    });
+    std::io::stdout().write_all(&printer.print_history(&entries)?)?;
+    Ok(())
+}
+
+#[cfg(target_family = "unix")]
+fn run_capture(
+    capture_args: &args::CaptureArgs,
+    args: &args::Args,
+    mut printer: Printer,
+) -> AppResult<()> {
+    let ignore = capture_args
+        .ignore
+        .iter()
+        .map(|pattern| pattern::Pattern::new(pattern, args.regex))
+        .collect::<AppResult<Vec<_>>>()?;
+    let (mut before, mut after) = capture::capture(capture_args)?;
+    for env in [&mut before, &mut after] {
+        env.retain(|record| !ignore.iter().any(|pattern| pattern.matches(&record.0)));
+    }
+    let diff = diff::EnvDiff::new(&before, &after);
 
-        if !args.variables.is_empty() {
-            env.filter_keys(&args.variables);
+    let output = match capture_args.format {
+        args::CaptureFormat::Report => printer.print_diff(&diff)?,
+        args::CaptureFormat::Unified => printer.print_unified_diff(&diff, "before", "after")?,
+        args::CaptureFormat::Json => {
+            printer.json = true;
+            printer.print_diff(&diff)?
         }
+        args::CaptureFormat::Sh => Printer::print_script(&diff),
+        args::CaptureFormat::Dotenv => Printer::print_dotenv(&diff),
+    };
+    std::io::stdout().write_all(&output)?;
+    Ok(())
+}
+
+fn run_diff(
+    diff_args: &args::DiffArgs,
+    args: &args::Args,
+    options: &source::ReadOptions,
+    mut printer: Printer,
+) -> AppResult<()> {
+    let ignore = diff_args
+        .ignore
+        .iter()
+        .map(|pattern| pattern::Pattern::new(pattern, args.regex))
+        .collect::<AppResult<Vec<_>>>()?;
+    let read = |source: &Source| -> AppResult<env::Env> {
+        #[cfg(procfs)]
+        let translated;
+        #[cfg(procfs)]
+        let source = match source {
+            Source::Pid(pid) => {
+                translated = Source::Pid(host_pid(args, *pid)?);
+                &translated
+            }
+            source => source,
+        };
+        let mut env = source.read(options)?;
+        env.retain(|record| !ignore.iter().any(|pattern| pattern.matches(&record.0)));
+        Ok(env)
+    };
+
+    let diff = diff::EnvDiff::compare(
+        &read(&diff_args.old)?,
+        &read(&diff_args.new)?,
+        diff_args.ignore_case,
+    );
 
-        env.sort_by_key(args.key_order);
-        env
+    let output = match diff_args.format {
+        args::DiffFormat::Report => printer.print_diff(&diff)?,
+        args::DiffFormat::Unified => {
+            printer.print_unified_diff(&diff, &diff_args.old.label(), &diff_args.new.label())?
+        }
+        args::DiffFormat::Json => {
+            printer.json = true;
+            printer.print_diff(&diff)?
+        }
     };
+    std::io::stdout().write_all(&output)?;
+
+    if !diff.is_empty() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn run_snapshot(
+    args: &args::Args,
+    source: &Source,
+    options: &source::ReadOptions,
+    printer: &Printer,
+) -> AppResult<()> {
+    let env = source.read(options)?;
+
+    if let Some(path) = &args.save_snapshot {
+        let excluded: &[&str] = if args.exclude_volatile {
+            &snapshot::VOLATILE
+        } else {
+            &[]
+        };
+        return snapshot::Snapshot::new(source, options, &env, excluded).save(path);
+    }
+
+    if let Some(path) = &args.against_snapshot {
+        let snapshot = snapshot::Snapshot::load(path)?;
+        let mut env = env;
+        env.retain(|record| {
+            !snapshot
+                .excluded()
+                .iter()
+                .any(|key| key.as_bytes() == record.0)
+        });
 
+        let diff = diff::EnvDiff::new(&snapshot.env()?, &env);
+        let output = printer.print_diff(&diff)?;
+        std::io::stdout().write_all(&output)?;
+        if !diff.is_empty() {
+            std::process::exit(1);
+        }
+    }
+    Ok(())
+}
+
+fn refine(env: &mut env::Env, args: &args::Args) {
+    if args.json && !args.variables.is_empty() {
+        // Keys of a JSON object cannot repeat
+        let mut variables: Vec<String> = Vec::new();
+        for variable in &args.variables {
+            if !variables.contains(variable) {
+                variables.push(variable.clone());
+            }
+        }
+        env.filter_keys(&variables);
+    } else if !args.variables.is_empty() {
+        env.filter_keys(&args.variables);
+    }
+
+    env.sort_by_key(args.key_order);
+}
+
+fn create_printer(args: &args::Args) -> Printer {
     let mut printer = Printer::default();
 
     // Override default printer behaviors
-    if args.null {
-        printer.null = args.null;
+    if args.null_output() {
+        printer.null = true;
     }
 
     if args.json {
@@ -87,16 +711,17 @@ fn main() -> AppResult<()> {
         printer.include_keys = false;
     }
 
-    printer.color = args.color;
-    if let Some(escape) = args.escape {
-        printer.escape = escape;
-    }
-
-    let output = printer.print(&env)?;
-    Stdout::write(&mut std::io::stdout(), &output)?;
-
-    if output.is_empty() {
-        std::process::exit(1);
-    }
-    Ok(())
+    // Installed as `printenv` or `env`, output is plain like that of GNU coreutils
+    let gnu = matches!(args::program_name().as_deref(), Some("printenv" | "env"));
+    printer.color = match args.color {
+        args::ColorMode::Auto if gnu => args::ColorMode::Never,
+        color => color,
+    };
+    let plain = args.null_output() || gnu;
+    printer.escape = args.escape.unwrap_or(if plain {
+        args::EscapeMode::No
+    } else {
+        args::EscapeMode::Yes
+    });
+    printer
 }
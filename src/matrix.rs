@@ -0,0 +1,115 @@
+use crate::args::KeyOrder;
+use crate::env::{Env, RecordPair};
+use crate::platform_ext::u8_vec_to_string;
+use serde::ser::SerializeMap;
+use serde::{Serialize, Serializer};
+
+/// Name of a variable and its value in each source, if present
+pub type Row = (Vec<u8>, Vec<Option<Vec<u8>>>);
+
+/// Values of every variable across several sources, one row per variable
+#[derive(Debug, PartialEq, Eq)]
+pub struct Matrix {
+    pub sources: Vec<String>,
+    pub rows: Vec<Row>,
+}
+
+impl Matrix {
+    /// Rows follow the order in which variables first appear across the sources
+    pub fn new(columns: &[(String, Env)]) -> Self {
+        let mut rows: Vec<Row> = Vec::new();
+        for (i, (_, env)) in columns.iter().enumerate() {
+            for RecordPair(key, value) in env.iter() {
+                let index = rows.iter().position(|(k, _)| k == key).unwrap_or_else(|| {
+                    rows.push((key.clone(), vec![None; columns.len()]));
+                    rows.len() - 1
+                });
+                rows[index].1[i] = Some(value.clone());
+            }
+        }
+
+        Self {
+            sources: columns.iter().map(|(label, _)| label.clone()).collect(),
+            rows,
+        }
+    }
+
+    pub fn sort_by_key(&mut self, key_order: KeyOrder) {
+        match key_order {
+            KeyOrder::Asc => self.rows.sort_by(|a, b| a.0.cmp(&b.0)),
+            KeyOrder::Desc => self.rows.sort_by(|a, b| b.0.cmp(&a.0)),
+            KeyOrder::None => (),
+        }
+    }
+
+    /// Drop variables having the same value in every source
+    pub fn retain_differences(&mut self) {
+        self.rows
+            .retain(|(_, cells)| cells.iter().any(|cell| *cell != cells[0]));
+    }
+}
+
+struct Rows<'a>(&'a [Row]);
+
+impl Serialize for Rows<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let lossy = |bytes: &[u8]| u8_vec_to_string(bytes).unwrap_or_else(|this| this);
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (key, cells) in self.0 {
+            let cells: Vec<Option<String>> = cells
+                .iter()
+                .map(|cell| cell.as_deref().map(lossy))
+                .collect();
+            map.serialize_entry(&lossy(key), &cells)?;
+        }
+        map.end()
+    }
+}
+
+impl Serialize for Matrix {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("sources", &self.sources)?;
+        map.serialize_entry("variables", &Rows(&self.rows))?;
+        map.end()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Matrix;
+    use crate::args::KeyOrder;
+    use crate::env::Env;
+
+    #[test]
+    fn rows_across_sources() {
+        let mut matrix = Matrix::new(&[
+            ("a".to_owned(), Env::from(Vec::from("X=1\0Y=2\0"))),
+            ("b".to_owned(), Env::from(Vec::from("Y=2\0Z=3\0X=1\0A=0\0"))),
+        ]);
+        assert_eq!(
+            matrix.rows,
+            vec![
+                (
+                    b"X".to_vec(),
+                    vec![Some(b"1".to_vec()), Some(b"1".to_vec())]
+                ),
+                (
+                    b"Y".to_vec(),
+                    vec![Some(b"2".to_vec()), Some(b"2".to_vec())]
+                ),
+                (b"Z".to_vec(), vec![None, Some(b"3".to_vec())]),
+                (b"A".to_vec(), vec![None, Some(b"0".to_vec())]),
+            ]
+        );
+
+        matrix.retain_differences();
+        assert_eq!(
+            serde_json::to_string(&matrix).unwrap(),
+            r#"{"sources":["a","b"],"variables":{"Z":[null,"3"],"A":[null,"0"]}}"#
+        );
+
+        matrix.sort_by_key(KeyOrder::Asc);
+        assert_eq!(matrix.rows[0].0, b"A".to_vec());
+    }
+}
@@ -0,0 +1,135 @@
+use crate::definition::{AppError, AppResult};
+
+/// A shell-style glob or a regular expression, matched against raw bytes
+#[derive(Debug)]
+pub enum Pattern {
+    /// Must match the whole input. Supports `*`, `?`, `[...]`, `[!...]` and backslash escapes
+    Glob(Vec<u8>),
+    /// Matches anywhere in the input unless anchored
+    Regex(regex::bytes::Regex),
+}
+
+impl Pattern {
+    pub fn new(pattern: &str, regex: bool) -> AppResult<Self> {
+        if regex {
+            regex::bytes::Regex::new(pattern)
+                .map(Self::Regex)
+                .map_err(|err| AppError::InvalidPattern(err.to_string()))
+        } else {
+            Ok(Self::Glob(pattern.as_bytes().to_vec()))
+        }
+    }
+
+    pub fn matches(&self, input: &[u8]) -> bool {
+        match self {
+            Self::Glob(glob) => glob_matches(glob, input),
+            Self::Regex(regex) => regex.is_match(input),
+        }
+    }
+}
+
+fn glob_matches(glob: &[u8], input: &[u8]) -> bool {
+    // Position to resume from when a mismatch happens after a `*`
+    let mut backtrack: Option<(usize, usize)> = None;
+    let (mut g, mut i) = (0, 0);
+
+    while i < input.len() {
+        let step = match glob.get(g) {
+            Some(b'*') => {
+                backtrack = Some((g, i));
+                g += 1;
+                continue;
+            }
+            Some(b'?') => Some(1),
+            Some(b'[') => match_class(&glob[g..], input[i]),
+            Some(b'\\') if g + 1 < glob.len() => (glob[g + 1] == input[i]).then_some(2),
+            Some(c) => (*c == input[i]).then_some(1),
+            None => None,
+        };
+
+        match (step, backtrack) {
+            (Some(step), _) => {
+                g += step;
+                i += 1;
+            }
+            (None, Some((star, matched))) => {
+                g = star + 1;
+                i = matched + 1;
+                backtrack = Some((star, matched + 1));
+            }
+            (None, None) => return false,
+        }
+    }
+
+    glob[g..].iter().all(|c| *c == b'*')
+}
+
+/// Length of the bracket expression at the start of `glob` if `c` is in the class
+fn match_class(glob: &[u8], c: u8) -> Option<usize> {
+    let mut pos = 1;
+    let negated = matches!(glob.get(pos), Some(b'!' | b'^'));
+    if negated {
+        pos += 1;
+    }
+
+    let mut found = false;
+    let mut first = true;
+    loop {
+        let start = *glob.get(pos)?;
+        if start == b']' && !first {
+            break;
+        }
+        first = false;
+        if glob.get(pos + 1) == Some(&b'-') && glob.get(pos + 2).is_some_and(|end| *end != b']') {
+            found |= (start..=glob[pos + 2]).contains(&c);
+            pos += 3;
+        } else {
+            found |= start == c;
+            pos += 1;
+        }
+    }
+
+    (found != negated).then_some(pos + 1)
+}
+
+#[cfg(test)]
+mod test {
+    use super::Pattern;
+
+    #[test]
+    fn glob() {
+        let cases = [
+            ("AWS_*", "AWS_PROFILE", true),
+            ("AWS_*", "MY_AWS_PROFILE", false),
+            ("*_PROFILE", "AWS_PROFILE", true),
+            ("LD_PRELOAD", "LD_PRELOAD", true),
+            ("LD_PRELOAD", "LD_PRELOAD2", false),
+            ("L?_*", "LD_LIBRARY_PATH", true),
+            ("*a*b*", "xxaxxbxx", true),
+            ("*a*b", "xxaxxbxx", false),
+            ("[A-C]X", "BX", true),
+            ("[!A-C]X", "BX", false),
+            ("[]]", "]", true),
+            ("\\*", "*", true),
+            ("\\*", "a", false),
+            ("", "", true),
+            ("*", "", true),
+        ];
+        for (glob, input, expected) in cases {
+            let pattern = Pattern::new(glob, false).unwrap();
+            assert_eq!(
+                pattern.matches(input.as_bytes()),
+                expected,
+                "{glob} {input}"
+            );
+        }
+    }
+
+    #[test]
+    fn regex() {
+        let pattern = Pattern::new("^old-", true).unwrap();
+        assert!(pattern.matches(b"old-profile"));
+        assert!(!pattern.matches(b"new-old-profile"));
+        assert!(Pattern::new("(", true).is_err());
+    }
+}
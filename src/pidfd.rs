@@ -0,0 +1,63 @@
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::time::Duration;
+
+use crate::definition::AppResult;
+
+/// A file descriptor referring to a process, which stays valid even if its PID gets reused
+#[derive(Debug)]
+pub struct PidFd(OwnedFd);
+
+impl PidFd {
+    pub fn open(pid: u32) -> AppResult<Self> {
+        let pid = libc::pid_t::try_from(pid)
+            .map_err(|_| std::io::Error::from_raw_os_error(libc::ESRCH))?;
+        let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        let fd = i32::try_from(fd).map_err(|_| std::io::Error::from_raw_os_error(libc::EBADF))?;
+        Ok(Self(unsafe { OwnedFd::from_raw_fd(fd) }))
+    }
+
+    /// Wait up to `timeout` for the process to exit, and return whether it did
+    pub fn wait_exit(&self, timeout: Duration) -> AppResult<bool> {
+        let mut pollfd = libc::pollfd {
+            fd: self.0.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout = i32::try_from(timeout.as_millis()).unwrap_or(i32::MAX);
+        loop {
+            let ret = unsafe { libc::poll(&raw mut pollfd, 1, timeout) };
+            if ret >= 0 {
+                return Ok(ret > 0);
+            }
+            let err = std::io::Error::last_os_error();
+            if err.kind() != std::io::ErrorKind::Interrupted {
+                return Err(err.into());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PidFd;
+    use std::time::Duration;
+
+    #[test]
+    fn wait_for_child() {
+        // cat blocks on its stdin until it is killed
+        let mut child = std::process::Command::new("cat")
+            .stdin(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        let pidfd = PidFd::open(child.id()).unwrap();
+        assert!(!pidfd.wait_exit(Duration::ZERO).unwrap());
+        assert!(!pidfd.wait_exit(Duration::from_millis(50)).unwrap());
+
+        child.kill().unwrap();
+        assert!(pidfd.wait_exit(Duration::from_secs(10)).unwrap());
+        child.wait().unwrap();
+    }
+}
@@ -14,6 +14,18 @@ pub fn os_string_to_u8_vec(os_string: &OsString) -> Vec<u8> {
     os_string.as_bytes().to_vec()
 }
 
+#[cfg(target_family = "unix")]
+pub fn u8_vec_to_os_string(bytes: &[u8]) -> OsString {
+    use std::os::unix::ffi::OsStrExt;
+    std::ffi::OsStr::from_bytes(bytes).to_owned()
+}
+
+#[cfg(target_os = "wasi")]
+pub fn u8_vec_to_os_string(bytes: &[u8]) -> OsString {
+    use std::os::wasi::ffi::OsStrExt;
+    std::ffi::OsStr::from_bytes(bytes).to_owned()
+}
+
 #[cfg(any(target_family = "unix", target_os = "wasi"))]
 pub fn u8_vec_to_string(bytes: &[u8]) -> Utf8DecodeResult {
     std::str::from_utf8(bytes).map_or_else(
@@ -36,6 +48,12 @@ pub fn os_string_to_u8_vec(os_string: &OsString) -> Vec<u8> {
     os_string.to_str().unwrap().as_bytes().to_vec()
 }
 
+#[cfg(target_family = "windows")]
+pub fn u8_vec_to_os_string(bytes: &[u8]) -> OsString {
+    // On Windows, OsString must be valid Unicode
+    OsString::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
 #[cfg(target_family = "windows")]
 #[allow(clippy::unnecessary_wraps)]
 pub fn u8_vec_to_string(bytes: &[u8]) -> Utf8DecodeResult {
@@ -43,6 +61,27 @@ pub fn u8_vec_to_string(bytes: &[u8]) -> Utf8DecodeResult {
     Ok(std::str::from_utf8(bytes).unwrap().into())
 }
 
+#[cfg(target_family = "unix")]
+pub fn hostname() -> Option<String> {
+    let mut buffer = [0u8; 256];
+    let ret = unsafe { libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len()) };
+    if ret != 0 {
+        return None;
+    }
+    let len = buffer.iter().position(|c| *c == 0).unwrap_or(buffer.len());
+    String::from_utf8(buffer[..len].to_vec()).ok()
+}
+
+#[cfg(target_family = "windows")]
+pub fn hostname() -> Option<String> {
+    std::env::var("COMPUTERNAME").ok()
+}
+
+#[cfg(target_os = "wasi")]
+pub const fn hostname() -> Option<String> {
+    None
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
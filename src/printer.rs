@@ -1,10 +1,43 @@
 use crate::AppResult;
 use crate::args::{ColorMode, EscapeMode};
-use crate::env::Env;
+#[cfg(procfs)]
+use crate::blame::Blame;
+#[cfg(procfs)]
+use crate::context::Context;
+use crate::definition::{AppError, ErrorKind};
+use crate::diff::EnvDiff;
+#[cfg(procfs)]
+use crate::doctor::{Finding, Status};
+#[cfg(target_family = "unix")]
+use crate::dotenv;
+#[cfg(target_family = "unix")]
+use crate::env::RecordPair;
+use crate::env::{Env, ProcessEnv};
+#[cfg(target_family = "unix")]
+use crate::history::Entry;
+use crate::matrix::Matrix;
 use crate::platform_ext::u8_vec_to_string;
-use colored::{ColoredString, Colorize};
+#[cfg(target_family = "unix")]
+use crate::shell;
+use colored::{Color, ColoredString, Colorize};
+use std::fmt::Write as _;
 use std::io::Write;
 
+#[cfg(procfs)]
+#[derive(serde::Serialize)]
+struct Envelope<'a> {
+    context: &'a Context,
+    environment: &'a Env,
+}
+
+#[derive(serde::Serialize)]
+struct ErrorReport<'a> {
+    error: String,
+    kind: ErrorKind,
+    exit_code: i32,
+    hints: &'a [String],
+}
+
 pub struct Printer {
     pub null: bool,
     pub json: bool,
@@ -25,6 +58,27 @@ impl Default for Printer {
     }
 }
 
+#[cfg(target_family = "unix")]
+fn lossy(bytes: &[u8]) -> String {
+    u8_vec_to_string(bytes).unwrap_or_else(|this| this)
+}
+
+/// `key` if it is a shell variable name, which can be written into scripts as it is.
+/// Other keys are reported on stderr
+#[cfg(target_family = "unix")]
+fn shell_name(key: &[u8]) -> Option<&str> {
+    let name = std::str::from_utf8(key)
+        .ok()
+        .filter(|name| shell::is_name(name));
+    if name.is_none() {
+        eprintln!(
+            "printenv2: skipping {}, which is not a valid variable name",
+            lossy(key).escape_debug()
+        );
+    }
+    name
+}
+
 enum FormatField {
     Key,
     Value,
@@ -95,13 +149,407 @@ impl Printer {
             Ok(output)
         }
     }
+
+    pub fn print_diff(&self, diff: &EnvDiff) -> AppResult<Vec<u8>> {
+        if self.json {
+            return Ok(serde_json::to_vec(diff)?);
+        }
+
+        let mut output = Vec::new();
+        for record in &diff.added {
+            writeln!(
+                &mut output,
+                "{} {}={}",
+                "+".green(),
+                self.format(&record.0, &FormatField::Key),
+                self.format(&record.1, &FormatField::Value),
+            )?;
+        }
+        for record in &diff.removed {
+            writeln!(
+                &mut output,
+                "{} {}={}",
+                "-".red(),
+                self.format(&record.0, &FormatField::Key),
+                self.format(&record.1, &FormatField::Value),
+            )?;
+        }
+        for (key, old, new) in &diff.changed {
+            writeln!(
+                &mut output,
+                "{} {}={} {} {}",
+                "~".yellow(),
+                self.format(key, &FormatField::Key),
+                self.format(old, &FormatField::Value),
+                "->".white(),
+                self.format(new, &FormatField::Value),
+            )?;
+        }
+        Ok(output)
+    }
+
+    /// Unified-diff style output, with one line per variable and no context lines
+    pub fn print_unified_diff(
+        &self,
+        diff: &EnvDiff,
+        old_label: &str,
+        new_label: &str,
+    ) -> AppResult<Vec<u8>> {
+        let mut lines: Vec<(&[u8], bool, &[u8])> = Vec::new();
+        lines.extend(diff.removed.iter().map(|r| (&r.0[..], false, &r.1[..])));
+        lines.extend(diff.added.iter().map(|r| (&r.0[..], true, &r.1[..])));
+        for (key, old, new) in &diff.changed {
+            lines.push((key, false, old));
+            lines.push((key, true, new));
+        }
+        // Stable, so a removal stays in front of the addition for the same key
+        lines.sort_by_key(|(key, _, _)| *key);
+
+        let mut output = Vec::new();
+        writeln!(&mut output, "{}", format!("--- {old_label}").bold())?;
+        writeln!(&mut output, "{}", format!("+++ {new_label}").bold())?;
+        for (key, added, value) in lines {
+            let line = format!("{}={}", self.plain(key), self.plain(value));
+            if added {
+                writeln!(&mut output, "{}", format!("+{line}").green())?;
+            } else {
+                writeln!(&mut output, "{}", format!("-{line}").red())?;
+            }
+        }
+        Ok(output)
+    }
+
+    /// POSIX shell commands which turn the old environment into the new one
+    #[cfg(target_family = "unix")]
+    pub fn print_script(diff: &EnvDiff) -> Vec<u8> {
+        let mut output = String::new();
+        let set = diff
+            .added
+            .iter()
+            .map(|RecordPair(key, value)| (key, value))
+            .chain(diff.changed.iter().map(|(key, _, value)| (key, value)));
+        for (key, value) in set {
+            if let Some(name) = shell_name(key) {
+                writeln!(output, "export {name}={}", shell::quote(&lossy(value))).unwrap();
+            }
+        }
+        for RecordPair(key, _) in &diff.removed {
+            if let Some(name) = shell_name(key) {
+                writeln!(output, "unset {name}").unwrap();
+            }
+        }
+        output.into_bytes()
+    }
+
+    /// Variables which were set or changed, as a dotenv file. Removals cannot be expressed, and become comments
+    #[cfg(target_family = "unix")]
+    pub fn print_dotenv(diff: &EnvDiff) -> Vec<u8> {
+        let mut output = String::new();
+        let set = diff
+            .added
+            .iter()
+            .map(|RecordPair(key, value)| (key, value))
+            .chain(diff.changed.iter().map(|(key, _, value)| (key, value)));
+        for (key, value) in set {
+            if let Some(name) = shell_name(key) {
+                writeln!(output, "{name}={}", dotenv::quote(&lossy(value))).unwrap();
+            }
+        }
+        for RecordPair(key, _) in &diff.removed {
+            if let Some(name) = shell_name(key) {
+                writeln!(output, "# unset {name}").unwrap();
+            }
+        }
+        output.into_bytes()
+    }
+
+    fn plain(&self, bytes: &[u8]) -> String {
+        let string = u8_vec_to_string(bytes).unwrap_or_else(|this| this);
+        if self.escape == EscapeMode::No {
+            string
+        } else {
+            Self::escape(&string)
+        }
+    }
+
+    pub fn print_matrix(&self, matrix: &Matrix) -> AppResult<Vec<u8>> {
+        if self.json {
+            return Ok(serde_json::to_vec(matrix)?);
+        }
+
+        // Cells of other sources show "=" when they are the same as the first source
+        let mut table: Vec<Vec<(String, Color)>> = vec![
+            std::iter::once("NAME".to_owned())
+                .chain(matrix.sources.iter().cloned())
+                .map(|header| (header, Color::Cyan))
+                .collect(),
+        ];
+        for (key, cells) in &matrix.rows {
+            let mut row = vec![(self.plain(key), Color::Yellow)];
+            for (i, cell) in cells.iter().enumerate() {
+                row.push(match cell {
+                    None => ("<absent>".to_owned(), Color::Red),
+                    Some(_) if i > 0 && *cell == cells[0] => ("=".to_owned(), Color::BrightBlack),
+                    Some(value) if i > 0 => (self.plain(value), Color::Magenta),
+                    Some(value) => (self.plain(value), Color::BrightWhite),
+                });
+            }
+            table.push(row);
+        }
+
+        let widths: Vec<usize> = (0..=matrix.sources.len())
+            .map(|column| {
+                table
+                    .iter()
+                    .map(|row| row[column].0.chars().count())
+                    .max()
+                    .unwrap_or_default()
+            })
+            .collect();
+
+        let mut output = Vec::new();
+        for row in table {
+            let last = row.len() - 1;
+            for (column, (text, color)) in row.into_iter().enumerate() {
+                let padding = if column == last {
+                    String::new()
+                } else {
+                    " ".repeat(widths[column] - text.chars().count() + 2)
+                };
+                write!(&mut output, "{}{padding}", text.color(color))?;
+            }
+            writeln!(&mut output)?;
+        }
+        Ok(output)
+    }
+
+    #[cfg(procfs)]
+    pub fn print_blame(&self, chain: &[Blame]) -> AppResult<Vec<u8>> {
+        if self.json {
+            return Ok(serde_json::to_vec(chain)?);
+        }
+
+        let mut output = Vec::new();
+        for blame in chain {
+            writeln!(
+                &mut output,
+                "{}",
+                format!("[{} {}]", blame.pid, blame.comm).cyan()
+            )?;
+            match &blame.changes {
+                Some(diff) => output.extend(self.print_diff(diff)?),
+                None => writeln!(&mut output, "{}", "(environment not readable)".dimmed())?,
+            }
+        }
+        Ok(output)
+    }
+
+    #[cfg(target_family = "unix")]
+    pub fn print_history(&self, entries: &[Entry]) -> AppResult<Vec<u8>> {
+        if self.json {
+            return Ok(serde_json::to_vec(entries)?);
+        }
+
+        let mut output = Vec::new();
+        for entry in entries {
+            writeln!(
+                &mut output,
+                "{} {} {}",
+                format!("[{} {}]", entry.time, entry.session).cyan(),
+                entry.cwd.dimmed(),
+                format!("$ {}", entry.command).bold(),
+            )?;
+            output.extend(self.print_diff(&entry.changes)?);
+        }
+        Ok(output)
+    }
+
+    #[cfg(procfs)]
+    pub fn print_findings(&self, findings: &[Finding]) -> AppResult<Vec<u8>> {
+        if self.json {
+            return Ok(serde_json::to_vec(findings)?);
+        }
+
+        let mut output = Vec::new();
+        for finding in findings {
+            let status = match finding.status {
+                Status::Ok => "ok".green(),
+                Status::Warning => "warning".yellow(),
+                Status::Blocked => "blocked".red(),
+            };
+            writeln!(
+                &mut output,
+                "[{status}] {}: {}",
+                finding.check.cyan(),
+                finding.detail
+            )?;
+        }
+        Ok(output)
+    }
+
+    /// A failure for stderr, as `printenv2: <message>` followed by hints, or a JSON object
+    pub fn print_error(&self, err: &AppError, hints: &[String]) -> Vec<u8> {
+        let kind = err.kind();
+        if self.json {
+            let report = ErrorReport {
+                error: err.to_string(),
+                kind,
+                exit_code: kind.exit_code(),
+                hints,
+            };
+            let mut output = serde_json::to_vec(&report).unwrap_or_default();
+            output.push(b'\n');
+            return output;
+        }
+
+        let mut output = format!("printenv2: {err}\n");
+        for hint in hints {
+            writeln!(output, "hint: {hint}").unwrap();
+        }
+        output.into_bytes()
+    }
+
+    /// The environment behind a header describing the process
+    #[cfg(procfs)]
+    pub fn print_context(&self, context: &Context, env: &Env) -> AppResult<Vec<u8>> {
+        if self.json {
+            return Ok(serde_json::to_vec(&Envelope {
+                context,
+                environment: env,
+            })?);
+        }
+
+        let mut header = format!("[{} {}", context.pid, context.comm);
+        if let Some(uid) = context.uid {
+            write!(header, " uid={uid}").unwrap();
+        }
+        if let Some(gid) = context.gid {
+            write!(header, " gid={gid}").unwrap();
+        }
+        header.push(']');
+        if let Some(argv) = &context.argv {
+            write!(header, " {}", Self::escape(&argv.join(" "))).unwrap();
+        }
+        let fields = [
+            ("exe", context.exe.clone()),
+            ("cwd", context.cwd.clone()),
+            ("started", context.start_time.clone()),
+            ("container", context.container.clone()),
+            (
+                "secure",
+                context.secure.map(|secure| {
+                    if secure {
+                        "yes, the loader ignored LD_* variables".to_owned()
+                    } else {
+                        "no".to_owned()
+                    }
+                }),
+            ),
+        ];
+        for (name, value) in fields {
+            if let Some(value) = value {
+                write!(header, "\n{name}: {}", Self::escape(&value)).unwrap();
+            }
+        }
+        for cgroup in &context.cgroups {
+            write!(header, "\ncgroup: {}", Self::escape(cgroup)).unwrap();
+        }
+
+        let mut output = Vec::new();
+        for line in header.lines() {
+            writeln!(&mut output, "{}", line.cyan())?;
+        }
+        writeln!(&mut output)?;
+        output.extend(self.print(env)?);
+        Ok(output)
+    }
+
+    pub fn print_processes(&self, processes: &[ProcessEnv]) -> AppResult<Vec<u8>> {
+        if self.json {
+            return Ok(serde_json::to_vec(processes)?);
+        }
+
+        let mut output = Vec::new();
+        for (i, process) in processes.iter().enumerate() {
+            if i > 0 {
+                writeln!(&mut output)?;
+            }
+            let mut header = format!("[{}", process.pid);
+            if let Some(comm) = &process.comm {
+                write!(header, " {comm}").unwrap();
+            }
+            if let Some(ppid) = process.ppid {
+                write!(header, " ppid={ppid}").unwrap();
+            }
+            if let Some(uid) = process.uid {
+                write!(header, " uid={uid}").unwrap();
+            }
+            header.push(']');
+            if let Some(cmdline) = &process.cmdline {
+                if self.escape == EscapeMode::No {
+                    write!(header, " {cmdline}").unwrap();
+                } else {
+                    write!(header, " {}", Self::escape(cmdline)).unwrap();
+                }
+            }
+            writeln!(&mut output, "{}", header.cyan())?;
+            output.extend(self.print(&process.env)?);
+        }
+        Ok(output)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::Printer;
     use crate::args::{ColorMode, EscapeMode};
-    use crate::env::Env;
+    use crate::diff::EnvDiff;
+    use crate::env::{Env, ProcessEnv, RecordPair};
+    use crate::matrix::Matrix;
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn script_and_dotenv() {
+        let diff = EnvDiff {
+            added: vec![RecordPair(b"VIRTUAL_ENV".to_vec(), b"/srv/venv".to_vec())],
+            removed: vec![RecordPair(b"PS1".to_vec(), b"$ ".to_vec())],
+            changed: vec![(
+                b"PATH".to_vec(),
+                b"/bin".to_vec(),
+                b"/srv/venv/bin:/bin".to_vec(),
+            )],
+        };
+        assert_eq!(
+            String::from_utf8(Printer::print_script(&diff)).unwrap(),
+            "export VIRTUAL_ENV='/srv/venv'\nexport PATH='/srv/venv/bin:/bin'\nunset PS1\n"
+        );
+        assert_eq!(
+            String::from_utf8(Printer::print_dotenv(&diff)).unwrap(),
+            "VIRTUAL_ENV=\"/srv/venv\"\nPATH=\"/srv/venv/bin:/bin\"\n# unset PS1\n"
+        );
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn hostile_keys() {
+        let diff = EnvDiff {
+            added: vec![
+                RecordPair(b"X=1;touch /tmp/pwned;Y".to_vec(), b"v".to_vec()),
+                RecordPair(b"OK".to_vec(), b"$(id)".to_vec()),
+                RecordPair(b"\xff".to_vec(), b"v".to_vec()),
+            ],
+            removed: vec![RecordPair(b"$(reboot)".to_vec(), b"v".to_vec())],
+            changed: vec![(b"A\nB".to_vec(), b"old".to_vec(), b"new".to_vec())],
+        };
+        assert_eq!(
+            String::from_utf8(Printer::print_script(&diff)).unwrap(),
+            "export OK='$(id)'\n"
+        );
+        assert_eq!(
+            String::from_utf8(Printer::print_dotenv(&diff)).unwrap(),
+            "OK=\"\\$(id)\"\n"
+        );
+    }
 
     #[test]
     fn escape() {
@@ -175,4 +623,131 @@ mod tests {
 
         assert_eq!(actual, Vec::from(r#"{"VAR1":"foo","VAR2":"bar"}"#));
     }
+
+    #[test]
+    fn group_by_process() {
+        let processes = vec![
+            ProcessEnv {
+                pid: 1,
+                comm: Some("init".to_owned()),
+                ppid: Some(0),
+                uid: Some(0),
+                cmdline: None,
+                env: Env::from(Vec::from("A=1\0")),
+            },
+            ProcessEnv {
+                pid: 2,
+                comm: None,
+                ppid: None,
+                uid: None,
+                cmdline: Some("/bin/sh -c true".to_owned()),
+                env: Env::from(Vec::from("B=2\0")),
+            },
+        ];
+
+        let printer = Printer {
+            color: ColorMode::Never,
+            ..Default::default()
+        };
+        let actual = printer.print_processes(&processes).unwrap();
+        assert_eq!(
+            actual,
+            Vec::from("[1 init ppid=0 uid=0]\nA=1\n\n[2] /bin/sh -c true\nB=2\n")
+        );
+
+        let printer = Printer {
+            json: true,
+            ..Default::default()
+        };
+        let actual = printer.print_processes(&processes).unwrap();
+        assert_eq!(
+            actual,
+            Vec::from(
+                r#"[{"pid":1,"comm":"init","ppid":0,"uid":0,"environment":{"A":"1"}},{"pid":2,"comm":null,"ppid":null,"uid":null,"cmdline":"/bin/sh -c true","environment":{"B":"2"}}]"#
+            )
+        );
+    }
+
+    #[test]
+    fn diff() {
+        let old = Env::from(Vec::from("GONE=2\0EDIT=3\0"));
+        let new = Env::from(Vec::from("EDIT=4\0NEW=5\0"));
+        let printer = Printer {
+            color: ColorMode::Never,
+            ..Default::default()
+        };
+        let actual = printer.print_diff(&EnvDiff::new(&old, &new)).unwrap();
+        assert_eq!(actual, Vec::from("+ NEW=5\n- GONE=2\n~ EDIT=3 -> 4\n"));
+    }
+
+    #[test]
+    fn matrix() {
+        let matrix = Matrix::new(&[
+            ("pid:1".to_owned(), Env::from(Vec::from("X=1\0Y=2\0"))),
+            ("pid:22".to_owned(), Env::from(Vec::from("X=1\0Y=3\0Z=4\0"))),
+        ]);
+        let printer = Printer {
+            color: ColorMode::Never,
+            ..Default::default()
+        };
+        let actual = printer.print_matrix(&matrix).unwrap();
+        assert_eq!(
+            String::from_utf8(actual).unwrap(),
+            "NAME  pid:1     pid:22\nX     1         =\nY     2         3\nZ     <absent>  4\n"
+        );
+    }
+
+    #[test]
+    fn unified_diff() {
+        let old = Env::from(Vec::from("GONE=2\0EDIT=3\0"));
+        let new = Env::from(Vec::from("EDIT=4\nx\0NEW=5\0"));
+        let printer = Printer {
+            color: ColorMode::Never,
+            ..Default::default()
+        };
+        let actual = printer
+            .print_unified_diff(&EnvDiff::new(&old, &new), "a", "b")
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(actual).unwrap(),
+            "--- a\n+++ b\n-EDIT=3\n+EDIT=4\\nx\n-GONE=2\n+NEW=5\n"
+        );
+    }
+
+    #[cfg(procfs)]
+    #[test]
+    fn context_header() {
+        use crate::context::Context;
+
+        let context = Context {
+            pid: 42,
+            comm: "sleep".to_owned(),
+            argv: Some(vec!["sleep".to_owned(), "30".to_owned()]),
+            exe: Some("/usr/bin/sleep".to_owned()),
+            cwd: None,
+            uid: Some(1000),
+            gid: None,
+            cgroups: vec!["/user.slice".to_owned()],
+            container: None,
+            start_time: None,
+            secure: Some(false),
+        };
+        let printer = Printer {
+            color: ColorMode::Never,
+            ..Default::default()
+        };
+        let env = Env::from(Vec::from("A=1\0"));
+        assert_eq!(
+            String::from_utf8(printer.print_context(&context, &env).unwrap()).unwrap(),
+            "[42 sleep uid=1000] sleep 30\nexe: /usr/bin/sleep\nsecure: no\ncgroup: /user.slice\n\nA=1\n"
+        );
+
+        let printer = Printer {
+            json: true,
+            ..Default::default()
+        };
+        let json = String::from_utf8(printer.print_context(&context, &env).unwrap()).unwrap();
+        assert!(json.starts_with(r#"{"context":{"pid":42,"#));
+        assert!(json.ends_with(r#""environment":{"A":"1"}}"#));
+    }
 }
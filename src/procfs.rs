@@ -0,0 +1,408 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::definition::{AppError, AppResult};
+
+/// Fields of `/proc/<pid>/stat` that printenv2 cares about
+#[derive(Debug, PartialEq, Eq)]
+pub struct Stat {
+    pub pid: u32,
+    pub comm: String,
+    pub state: char,
+    pub ppid: u32,
+    pub start_time: u64,
+}
+
+impl Stat {
+    fn parse(content: &str) -> Option<Self> {
+        // comm is wrapped in parentheses and may itself contain spaces and parentheses
+        let open = content.find('(')?;
+        let close = content.rfind(')')?;
+        let mut fields = content[close + 1..].split_whitespace();
+        Some(Self {
+            pid: content[..open].trim().parse().ok()?,
+            comm: content[open + 1..close].to_owned(),
+            state: fields.next()?.chars().next()?,
+            ppid: fields.next()?.parse().ok()?,
+            // starttime is the 22nd field, and ppid the 4th
+            start_time: fields.nth(22 - 4 - 1)?.parse().ok()?,
+        })
+    }
+
+    pub const fn process_id(&self) -> ProcessId {
+        ProcessId {
+            pid: self.pid,
+            start_time: self.start_time,
+        }
+    }
+}
+
+/// A process as it was when it was selected. Its PID may be reused once it exits, but not together with its start time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProcessId {
+    pub pid: u32,
+    pub start_time: u64,
+}
+
+/// Fields of `/proc/<pid>/status` that printenv2 cares about
+#[derive(Debug, PartialEq, Eq)]
+pub struct Status {
+    pub uid: u32,
+    pub euid: u32,
+    pub gid: u32,
+    /// Effective capabilities, as a bit set
+    pub cap_effective: u64,
+    /// PID in each PID namespace the process belongs to, from the one of procfs to the innermost
+    pub nspid: Vec<u32>,
+}
+
+impl Status {
+    fn parse(content: &str) -> Option<Self> {
+        let field = |name: &str| content.lines().find_map(|line| line.strip_prefix(name));
+        // Uid and Gid lines hold real, effective, saved set and filesystem IDs
+        let id = |name: &str, index: usize| -> Option<u32> {
+            field(name)?.split_whitespace().nth(index)?.parse().ok()
+        };
+        // NSpid is missing before Linux 4.1
+        let nspid = field("NSpid:")
+            .map(|line| {
+                line.split_whitespace()
+                    .filter_map(|pid| pid.parse().ok())
+                    .collect()
+            })
+            .unwrap_or_default();
+        Some(Self {
+            uid: id("Uid:", 0)?,
+            euid: id("Uid:", 1)?,
+            gid: id("Gid:", 0)?,
+            cap_effective: field("CapEff:")
+                .and_then(|caps| u64::from_str_radix(caps.trim(), 16).ok())
+                .unwrap_or_default(),
+            nspid,
+        })
+    }
+}
+
+const NATIVE_ROOT: &str = "/proc";
+
+/// A procfs mount: the one of printenv2 by default, or another one given with --proc-root,
+/// for example a host procfs mounted into a container
+#[derive(Debug, Clone)]
+pub struct Procfs {
+    root: PathBuf,
+}
+
+impl Default for Procfs {
+    fn default() -> Self {
+        Self::new(NATIVE_ROOT)
+    }
+}
+
+impl Procfs {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self { root: root.into() }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Whether PIDs in this procfs are the ones syscalls of printenv2 understand
+    pub fn is_native(&self) -> bool {
+        self.root == Path::new(NATIVE_ROOT)
+    }
+
+    /// PID of printenv2 itself, as seen in this procfs
+    pub fn self_pid(&self) -> u32 {
+        fs::read_link(self.root.join("self"))
+            .ok()
+            .and_then(|pid| pid.to_str()?.parse().ok())
+            .unwrap_or_else(std::process::id)
+    }
+
+    /// Translate a PID inside the innermost PID namespace of a process, for example a container, into a PID of this procfs
+    pub fn host_pid(&self, nspid: u32) -> AppResult<u32> {
+        let pids: Vec<u32> = self
+            .pids()?
+            .into_iter()
+            .filter(|pid| {
+                self.status(*pid).is_ok_and(|status| {
+                    status.nspid.len() > 1 && status.nspid.last() == Some(&nspid)
+                })
+            })
+            .collect();
+        match pids[..] {
+            [] => Err(AppError::NoProcessMatched(format!(
+                "PID {nspid} of a nested PID namespace"
+            ))),
+            [pid] => Ok(pid),
+            _ => Err(AppError::AmbiguousProcess(
+                pids.into_iter()
+                    .map(|pid| {
+                        (
+                            pid,
+                            self.stat(pid).map(|stat| stat.comm).unwrap_or_default(),
+                        )
+                    })
+                    .collect(),
+            )),
+        }
+    }
+
+    pub fn path(&self, pid: u32, entry: &str) -> PathBuf {
+        self.root.join(pid.to_string()).join(entry)
+    }
+
+    /// All PIDs currently visible in procfs, in ascending order
+    pub fn pids(&self) -> AppResult<Vec<u32>> {
+        let mut pids: Vec<u32> = fs::read_dir(&self.root)?
+            .filter_map(Result::ok)
+            .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+            .collect();
+        pids.sort_unstable();
+        Ok(pids)
+    }
+
+    pub fn stat(&self, pid: u32) -> AppResult<Stat> {
+        let content = fs::read_to_string(self.path(pid, "stat"))?;
+        Stat::parse(&content).ok_or_else(|| AppError::Procfs(format!("malformed stat of {pid}")))
+    }
+
+    pub fn status(&self, pid: u32) -> AppResult<Status> {
+        let content = fs::read_to_string(self.path(pid, "status"))?;
+        Status::parse(&content)
+            .ok_or_else(|| AppError::Procfs(format!("malformed status of {pid}")))
+    }
+
+    pub fn environ(&self, pid: u32) -> AppResult<Vec<u8>> {
+        Ok(fs::read(self.path(pid, "environ"))?)
+    }
+
+    /// Path of the executable, if it can be resolved
+    pub fn exe(&self, pid: u32) -> AppResult<PathBuf> {
+        Ok(fs::read_link(self.path(pid, "exe"))?)
+    }
+
+    /// Command line arguments
+    pub fn argv(&self, pid: u32) -> AppResult<Vec<String>> {
+        let content = fs::read(self.path(pid, "cmdline"))?;
+        Ok(content
+            .split(|c| *c == 0)
+            .filter(|arg| !arg.is_empty())
+            .map(|arg| String::from_utf8_lossy(arg).into_owned())
+            .collect())
+    }
+
+    /// Command line arguments, joined with spaces
+    pub fn cmdline(&self, pid: u32) -> AppResult<String> {
+        Ok(self.argv(pid)?.join(" "))
+    }
+
+    pub fn cwd(&self, pid: u32) -> AppResult<PathBuf> {
+        Ok(fs::read_link(self.path(pid, "cwd"))?)
+    }
+
+    /// Auxiliary vector which the kernel passed to the process, as type and value pairs
+    pub fn auxv(&self, pid: u32) -> AppResult<Vec<(usize, usize)>> {
+        const WORD: usize = std::mem::size_of::<usize>();
+        let content = fs::read(self.path(pid, "auxv"))?;
+        let word = |bytes: &[u8]| usize::from_ne_bytes(bytes.try_into().unwrap_or([0; WORD]));
+        Ok(content
+            .chunks_exact(2 * WORD)
+            .map(|pair| (word(&pair[..WORD]), word(&pair[WORD..])))
+            .take_while(|(kind, _)| *kind != 0)
+            .collect())
+    }
+
+    /// Seconds since the Unix epoch when the system booted
+    pub fn boot_time(&self) -> AppResult<u64> {
+        let content = fs::read_to_string(self.root.join("stat"))?;
+        content
+            .lines()
+            .find_map(|line| line.strip_prefix("btime "))
+            .and_then(|btime| btime.trim().parse().ok())
+            .ok_or_else(|| AppError::Procfs("no btime in stat".to_owned()))
+    }
+
+    /// Targets of the open file descriptors of the process
+    pub fn fd_targets(&self, pid: u32) -> AppResult<Vec<PathBuf>> {
+        Ok(fs::read_dir(self.path(pid, "fd"))?
+            .filter_map(Result::ok)
+            .filter_map(|entry| fs::read_link(entry.path()).ok())
+            .collect())
+    }
+
+    /// Socket inodes listening on a TCP port, on both IPv4 and IPv6
+    pub fn tcp_listeners(&self, port: u16) -> AppResult<Vec<u64>> {
+        let mut inodes = Vec::new();
+        for table in ["net/tcp", "net/tcp6"] {
+            match fs::read_to_string(self.root.join(table)) {
+                Ok(content) => inodes.extend(parse_tcp_listeners(&content, port)),
+                // IPv6 may be disabled
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(inodes)
+    }
+
+    /// Socket inodes bound to a Unix socket path
+    pub fn unix_sockets(&self, path: &Path) -> AppResult<Vec<u64>> {
+        let content = fs::read_to_string(self.root.join("net/unix"))?;
+        Ok(parse_unix_sockets(&content, path))
+    }
+
+    /// Processes holding any of the socket inodes open
+    pub fn socket_holders(&self, inodes: &[u64]) -> AppResult<Vec<u32>> {
+        if inodes.is_empty() {
+            return Ok(Vec::new());
+        }
+        let targets: Vec<PathBuf> = inodes
+            .iter()
+            .map(|inode| PathBuf::from(format!("socket:[{inode}]")))
+            .collect();
+        self.file_holders(|target| targets.iter().any(|socket| socket == target))
+    }
+
+    /// Processes having an open file descriptor whose target satisfies `predicate`
+    pub fn file_holders<F: Fn(&Path) -> bool>(&self, predicate: F) -> AppResult<Vec<u32>> {
+        Ok(self
+            .pids()?
+            .into_iter()
+            .filter(|pid| {
+                self.fd_targets(*pid)
+                    .is_ok_and(|targets| targets.iter().any(|target| predicate(target)))
+            })
+            .collect())
+    }
+
+    /// Cgroup paths of the process, one per hierarchy
+    pub fn cgroups(&self, pid: u32) -> AppResult<Vec<String>> {
+        let content = fs::read_to_string(self.path(pid, "cgroup"))?;
+        Ok(content
+            .lines()
+            .filter_map(|line| line.splitn(3, ':').nth(2))
+            .map(str::to_owned)
+            .collect())
+    }
+}
+
+/// Inodes of TCP sockets listening on `port`, from the contents of `/proc/net/tcp` or `/proc/net/tcp6`
+fn parse_tcp_listeners(content: &str, port: u16) -> Vec<u64> {
+    const TCP_LISTEN: &str = "0A";
+
+    content
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let local_port = fields.get(1)?.rsplit(':').next()?;
+            (u16::from_str_radix(local_port, 16).ok()? == port && *fields.get(3)? == TCP_LISTEN)
+                .then(|| fields.get(9)?.parse().ok())?
+        })
+        .collect()
+}
+
+/// Inodes of Unix sockets bound to `path`, from the contents of `/proc/net/unix`
+fn parse_unix_sockets(content: &str, path: &Path) -> Vec<u64> {
+    content
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            (Path::new(fields.get(7)?) == path).then(|| fields.get(6)?.parse().ok())?
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Procfs, Stat, Status, parse_tcp_listeners, parse_unix_sockets};
+    use crate::definition::AppError;
+    use std::path::Path;
+
+    #[test]
+    fn parse_stat() {
+        let content =
+            "1234 (my (weird) proc) S 1 1234 1234 0 -1 4194560 1 0 0 0 0 0 0 0 20 0 1 0 5678 0 0";
+        assert_eq!(
+            Stat::parse(content),
+            Some(Stat {
+                pid: 1234,
+                comm: "my (weird) proc".to_owned(),
+                state: 'S',
+                ppid: 1,
+                start_time: 5678,
+            })
+        );
+    }
+
+    #[test]
+    fn parse_status() {
+        let content = "Name:\tbash\nPid:\t42\nPPid:\t1\nUid:\t1000\t0\t0\t0\nGid:\t100\t100\t100\t100\nCapEff:\t0000000000080000\nNSpid:\t42\t7\n";
+        assert_eq!(
+            Status::parse(content),
+            Some(Status {
+                uid: 1000,
+                euid: 0,
+                gid: 100,
+                cap_effective: 1 << 19,
+                nspid: vec![42, 7],
+            })
+        );
+    }
+
+    #[test]
+    fn translate_nspid() {
+        let root = std::env::temp_dir().join(format!("printenv2-procfs-{}", std::process::id()));
+        for (pid, nspid) in [(10, "10"), (20, "20\t1"), (30, "30\t7"), (40, "40\t7")] {
+            let dir = root.join(pid.to_string());
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(
+                dir.join("status"),
+                format!("Uid:\t0\t0\t0\t0\nGid:\t0\t0\t0\t0\nNSpid:\t{nspid}\n"),
+            )
+            .unwrap();
+        }
+
+        let procfs = Procfs::new(&root);
+        assert!(!procfs.is_native());
+        let result = (procfs.host_pid(1), procfs.host_pid(10), procfs.host_pid(7));
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(result.0.unwrap(), 20);
+        assert!(matches!(result.1, Err(AppError::NoProcessMatched(_))));
+        assert!(matches!(result.2, Err(AppError::AmbiguousProcess(pids)) if pids.len() == 2));
+    }
+
+    #[test]
+    fn parse_own_stat() {
+        let procfs = Procfs::default();
+        assert_eq!(procfs.self_pid(), std::process::id());
+        let stat = procfs.stat(std::process::id()).unwrap();
+        assert_eq!(stat.pid, std::process::id());
+        assert_eq!(stat.ppid, std::os::unix::process::parent_id());
+    }
+
+    #[test]
+    fn parse_tcp() {
+        let content = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 0100007F:1F90 00000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 926 1 0 100 0 0 10 0
+   1: 0100007F:1F90 0100007F:D431 01 00000000:00000000 00:00000000 00000000  1000        0 927 1 0 100 0 0 10 0
+   2: 00000000:0016 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 662 1 0 100 0 0 10 0";
+        assert_eq!(parse_tcp_listeners(content, 8080), vec![926]);
+        assert_eq!(parse_tcp_listeners(content, 22), vec![662]);
+        assert!(parse_tcp_listeners(content, 443).is_empty());
+    }
+
+    #[test]
+    fn parse_unix() {
+        let content = "Num       RefCount Protocol Flags    Type St Inode Path
+00000000a499757d: 00000003 00000000 00000000 0001 03   924
+00000000e7b31e4f: 00000002 00000000 00010000 0001 01  6240 /run/x.sock";
+        assert_eq!(
+            parse_unix_sockets(content, Path::new("/run/x.sock")),
+            vec![6240]
+        );
+        assert!(parse_unix_sockets(content, Path::new("/run/y.sock")).is_empty());
+    }
+}
@@ -1,11 +1,111 @@
-use std::fs::File;
-use std::io::Read;
+use crate::definition::{AppError, AppResult};
+use crate::pidfd::PidFd;
+use crate::procfs::{ProcessId, Procfs};
 
-use crate::definition::AppResult;
+/// Read the environment of `pid`, making sure it was not replaced by another process meanwhile
+pub fn get_environment_string(procfs: &Procfs, pid: u32) -> AppResult<Vec<u8>> {
+    read_environ(procfs, procfs.stat(pid)?.process_id())
+}
+
+/// Read the environment of a process selected earlier, unless its PID has been reused since
+pub fn read_environ(procfs: &Procfs, process: ProcessId) -> AppResult<Vec<u8>> {
+    let pid = process.pid;
+    let is_same = || procfs.stat(pid).map(|stat| stat.process_id()).ok() == Some(process);
+
+    // PIDs of a foreign procfs belong to another namespace, where only the start time can be checked
+    let pidfd = if procfs.is_native() {
+        open_pidfd(pid)?
+    } else {
+        None
+    };
+    // The pidfd refers to whichever process holds the PID now, which must be the selected one
+    if !is_same() {
+        return Err(AppError::ProcessGone(pid));
+    }
+
+    let environ = match procfs.environ(pid) {
+        Ok(environ) => environ,
+        Err(AppError::StdIo(err))
+            if err.kind() == std::io::ErrorKind::NotFound
+                || err.raw_os_error() == Some(libc::ESRCH) =>
+        {
+            return Err(AppError::ProcessGone(pid));
+        }
+        Err(err) => return Err(err),
+    };
+
+    let exited = match &pidfd {
+        Some(pidfd) => pidfd.wait_exit(std::time::Duration::ZERO)?,
+        None => false,
+    };
+    if exited || !is_same() {
+        return Err(AppError::ProcessGone(pid));
+    }
+    Ok(environ)
+}
+
+/// A pidfd keeps referring to the original process, even once its PID is reused
+fn open_pidfd(pid: u32) -> AppResult<Option<PidFd>> {
+    match PidFd::open(pid) {
+        Ok(pidfd) => Ok(Some(pidfd)),
+        Err(AppError::StdIo(err)) if err.raw_os_error() == Some(libc::ESRCH) => {
+            Err(AppError::ProcessGone(pid))
+        }
+        // Kernels older than 5.3 lack pidfd_open
+        Err(AppError::StdIo(err)) if err.raw_os_error() == Some(libc::ENOSYS) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{get_environment_string, read_environ};
+    use crate::definition::AppError;
+    use crate::procfs::{ProcessId, Procfs};
+
+    #[test]
+    fn same_or_gone() {
+        let procfs = Procfs::default();
+        let process = procfs.stat(std::process::id()).unwrap().process_id();
+        assert_eq!(
+            read_environ(&procfs, process).unwrap(),
+            std::fs::read("/proc/self/environ").unwrap()
+        );
+
+        // Another process started at another time under the same PID
+        let reused = ProcessId {
+            start_time: process.start_time + 1,
+            ..process
+        };
+        assert!(matches!(
+            read_environ(&procfs, reused),
+            Err(AppError::ProcessGone(_))
+        ));
+
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let pid = child.id();
+        child.wait().unwrap();
+        assert!(matches!(
+            get_environment_string(&procfs, pid),
+            Err(AppError::ProcessGone(_) | AppError::StdIo(_))
+        ));
+    }
+
+    #[test]
+    fn foreign_root() {
+        let root = std::env::temp_dir().join(format!("printenv2-root-{}", std::process::id()));
+        let dir = root.join("1");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("stat"),
+            "1 (init) S 0 1 1 0 -1 4194560 1 0 0 0 0 0 0 0 20 0 1 0 42 0 0",
+        )
+        .unwrap();
+        std::fs::write(dir.join("environ"), "HOME=/root\0").unwrap();
 
-pub fn get_environment_string(pid: u32) -> AppResult<Vec<u8>> {
-    let mut file = File::open(format!("/proc/{pid}/environ"))?;
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer)?;
-    Ok(buffer)
+        let procfs = Procfs::new(&root);
+        let result = read_environ(&procfs, procfs.stat(1).unwrap().process_id());
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(result.unwrap(), b"HOME=/root\0");
+    }
 }
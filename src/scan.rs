@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+
+use crate::definition::AppResult;
+use crate::env::{Env, ProcessEnv};
+use crate::procfs::{ProcessId, Procfs};
+
+const KTHREADD_PID: u32 = 2;
+
+/// Every process except printenv2 itself and kernel threads, which have no environment.
+/// Processes which exit while being listed are left out
+pub fn all_processes(procfs: &Procfs) -> AppResult<Vec<ProcessId>> {
+    let self_pid = procfs.self_pid();
+    Ok(procfs
+        .pids()?
+        .into_iter()
+        .filter(|pid| *pid != self_pid && *pid != KTHREADD_PID)
+        .filter_map(|pid| procfs.stat(pid).ok())
+        .filter(|stat| stat.ppid != KTHREADD_PID)
+        .map(|stat| stat.process_id())
+        .collect())
+}
+
+/// `root` and all of its descendants, parents before children
+pub fn process_tree(procfs: &Procfs, root: u32) -> AppResult<Vec<ProcessId>> {
+    let root = procfs.stat(root)?.process_id();
+
+    let mut children: HashMap<u32, Vec<ProcessId>> = HashMap::new();
+    for pid in procfs.pids()? {
+        if let Ok(stat) = procfs.stat(pid) {
+            children
+                .entry(stat.ppid)
+                .or_default()
+                .push(stat.process_id());
+        }
+    }
+
+    let mut tree = Vec::new();
+    let mut stack = vec![root];
+    while let Some(process) = stack.pop() {
+        tree.push(process);
+        if let Some(children) = children.get(&process.pid) {
+            stack.extend(children.iter().rev());
+        }
+    }
+    Ok(tree)
+}
+
+/// Read environments of processes in parallel, keeping the order of `processes`
+pub fn read_processes(
+    procfs: &Procfs,
+    processes: &[ProcessId],
+) -> Vec<(u32, AppResult<ProcessEnv>)> {
+    let threads = std::thread::available_parallelism().map_or(1, NonZeroUsize::get);
+    let chunk_size = processes.len().div_ceil(threads).max(1);
+
+    std::thread::scope(|scope| {
+        // Collecting is needed to spawn all threads before joining the first one
+        #[allow(clippy::needless_collect)]
+        let handles: Vec<_> = processes
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|process| (process.pid, read_process(procfs, *process)))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    })
+}
+
+fn read_process(procfs: &Procfs, process: ProcessId) -> AppResult<ProcessEnv> {
+    let pid = process.pid;
+    let env = Env::from(crate::remote_linux_procfs::read_environ(procfs, process)?);
+    let stat = procfs.stat(pid).ok();
+    Ok(ProcessEnv {
+        pid,
+        ppid: stat.as_ref().map(|stat| stat.ppid),
+        comm: stat.map(|stat| stat.comm),
+        uid: procfs.status(pid).ok().map(|status| status.uid),
+        cmdline: None,
+        env,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{process_tree, read_processes};
+    use crate::procfs::{ProcessId, Procfs};
+
+    #[test]
+    fn tree_of_self() {
+        let procfs = Procfs::default();
+        let parent = std::os::unix::process::parent_id();
+        let tree = process_tree(&procfs, parent).unwrap();
+        assert_eq!(tree[0].pid, parent);
+        assert!(tree.iter().any(|process| process.pid == std::process::id()));
+    }
+
+    #[test]
+    fn read_self() {
+        let procfs = Procfs::default();
+        let pid = std::process::id();
+        let gone = ProcessId {
+            pid: u32::MAX,
+            start_time: 0,
+        };
+        let processes = read_processes(&procfs, &[procfs.stat(pid).unwrap().process_id(), gone]);
+        assert_eq!(processes.len(), 2);
+
+        let (first, second) = (&processes[0], &processes[1]);
+        assert_eq!(first.0, pid);
+        let process = first.1.as_ref().unwrap();
+        assert_eq!(process.ppid, Some(std::os::unix::process::parent_id()));
+        assert_eq!(second.0, u32::MAX);
+        assert!(second.1.is_err());
+    }
+}
@@ -0,0 +1,174 @@
+use std::ffi::OsStr;
+use std::io::Read;
+use std::path::PathBuf;
+
+use crate::definition::{AppError, AppResult};
+use crate::procfs::{ProcessId, Procfs, Stat};
+
+/// A way of picking target processes other than spelling out a PID
+#[derive(Debug, PartialEq, Eq)]
+pub enum Selector {
+    Name(String),
+    Pidfile(PathBuf),
+    Parent,
+    PpidOf(u32),
+    Cgroup(String),
+    Stdin,
+    Port(u16),
+    UnixSocket(PathBuf),
+    Holding(PathBuf),
+}
+
+impl Selector {
+    pub fn describe(&self) -> String {
+        match self {
+            Self::Name(name) => format!("--name {name}"),
+            Self::Pidfile(path) => format!("--pidfile {}", path.display()),
+            Self::Parent => "--parent".to_owned(),
+            Self::PpidOf(pid) => format!("--ppid-of {pid}"),
+            Self::Cgroup(cgroup) => format!("--cgroup {cgroup}"),
+            Self::Stdin => "--stdin-pids".to_owned(),
+            Self::Port(port) => format!("--port {port}"),
+            Self::UnixSocket(path) => format!("--unix-socket {}", path.display()),
+            Self::Holding(path) => format!("--holding {}", path.display()),
+        }
+    }
+
+    /// Matching processes, identified as they were when they matched.
+    /// Processes which exit before that are left out
+    pub fn resolve(&self, procfs: &Procfs) -> AppResult<Vec<ProcessId>> {
+        let pids = match self {
+            Self::Name(name) => {
+                // The start time comes first, so that a PID reused during the match is not taken for the match
+                let processes: Vec<ProcessId> = procfs
+                    .pids()?
+                    .into_iter()
+                    .filter_map(|pid| procfs.stat(pid).ok())
+                    .filter(|stat| name_matches(procfs, stat, name))
+                    .map(|stat| stat.process_id())
+                    .collect();
+                return self.found(processes);
+            }
+            Self::Pidfile(path) => parse_pids(&std::fs::read_to_string(path)?)?,
+            Self::Parent => vec![procfs.stat(procfs.self_pid())?.ppid],
+            Self::PpidOf(pid) => vec![procfs.stat(*pid)?.ppid],
+            Self::Cgroup(cgroup) => {
+                let cgroup = cgroup.trim_matches('/');
+                procfs
+                    .pids()?
+                    .into_iter()
+                    .filter(|pid| {
+                        procfs.cgroups(*pid).is_ok_and(|paths| {
+                            paths.iter().any(|path| path.trim_matches('/') == cgroup)
+                        })
+                    })
+                    .collect()
+            }
+            Self::Stdin => {
+                let mut content = String::new();
+                std::io::stdin().lock().read_to_string(&mut content)?;
+                parse_pids(&content)?
+            }
+            Self::Port(port) => procfs.socket_holders(&procfs.tcp_listeners(*port)?)?,
+            Self::UnixSocket(path) => {
+                // Sockets are listed under the path they were bound to, which may or may not go through symlinks
+                let absolute = std::path::absolute(path)?;
+                let mut inodes = procfs.unix_sockets(&absolute)?;
+                if let Ok(real) = std::fs::canonicalize(path)
+                    && real != absolute
+                {
+                    inodes.extend(procfs.unix_sockets(&real)?);
+                }
+                procfs.socket_holders(&inodes)?
+            }
+            Self::Holding(path) => {
+                let path = std::fs::canonicalize(path)?;
+                procfs.file_holders(|target| target == path)?
+            }
+        };
+
+        self.found(
+            pids.into_iter()
+                .filter_map(|pid| procfs.stat(pid).ok())
+                .map(|stat| stat.process_id())
+                .collect(),
+        )
+    }
+
+    fn found(&self, processes: Vec<ProcessId>) -> AppResult<Vec<ProcessId>> {
+        if processes.is_empty() {
+            Err(AppError::NoProcessMatched(self.describe()))
+        } else {
+            Ok(processes)
+        }
+    }
+}
+
+fn name_matches(procfs: &Procfs, stat: &Stat, name: &str) -> bool {
+    if stat.pid == procfs.self_pid() {
+        return false;
+    }
+    stat.comm == name
+        || procfs
+            .exe(stat.pid)
+            .is_ok_and(|exe| exe.file_name() == Some(OsStr::new(name)))
+}
+
+fn parse_pids(content: &str) -> AppResult<Vec<u32>> {
+    content
+        .split_whitespace()
+        .map(|word| {
+            word.parse()
+                .map_err(|_| AppError::InvalidPid(word.to_owned()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Selector, parse_pids};
+    use crate::procfs::Procfs;
+    use std::path::PathBuf;
+
+    #[test]
+    fn pids_from_text() {
+        assert_eq!(parse_pids("12\n34 56\n").unwrap(), vec![12, 34, 56]);
+        assert!(parse_pids("12 abc").is_err());
+    }
+
+    #[test]
+    fn resolve_parent_of_self() {
+        let procfs = Procfs::default();
+        let processes = Selector::PpidOf(std::process::id())
+            .resolve(&procfs)
+            .unwrap();
+        assert_eq!(
+            processes,
+            vec![
+                procfs
+                    .stat(std::os::unix::process::parent_id())
+                    .unwrap()
+                    .process_id()
+            ]
+        );
+    }
+
+    #[test]
+    fn resolve_relative_unix_socket() {
+        let procfs = Procfs::default();
+        let name = format!("printenv2-selector-{}.sock", std::process::id());
+        let listener = std::os::unix::net::UnixListener::bind(
+            std::env::current_dir().unwrap().join("target").join(&name),
+        )
+        .unwrap();
+
+        let relative = PathBuf::from("target/../target").join(&name);
+        let processes = Selector::UnixSocket(relative).resolve(&procfs);
+        drop(listener);
+        std::fs::remove_file(PathBuf::from("target").join(&name)).unwrap();
+        assert_eq!(
+            processes.unwrap(),
+            vec![procfs.stat(std::process::id()).unwrap().process_id()]
+        );
+    }
+}
@@ -0,0 +1,55 @@
+use crate::args::Shell;
+
+/// Quote `value` for POSIX shells, which take everything between single quotes literally
+pub fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Quote `value` for fish, where `\` and `'` are escaped within single quotes
+fn quote_fish(value: &str) -> String {
+    format!("'{}'", value.replace('\\', r"\\").replace('\'', r"\'"))
+}
+
+/// Whether `key` is a name a shell variable can have: a letter or `_`, then letters, digits and `_`
+pub fn is_name(key: &str) -> bool {
+    key.chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Command which exports `key` with `value`
+pub fn set(shell: Shell, key: &str, value: &str) -> String {
+    match shell {
+        Shell::Bash | Shell::Zsh => format!("export {key}={};", quote(value)),
+        Shell::Fish => format!("set -gx {key} {};", quote_fish(value)),
+    }
+}
+
+/// Command which removes `key`
+pub fn unset(shell: Shell, key: &str) -> String {
+    match shell {
+        Shell::Bash | Shell::Zsh => format!("unset {key};"),
+        Shell::Fish => format!("set -e {key};"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{is_name, quote, quote_fish};
+
+    #[test]
+    fn quoting() {
+        assert_eq!(quote("plain"), "'plain'");
+        assert_eq!(quote("it's $HOME"), r"'it'\''s $HOME'");
+        assert_eq!(quote_fish(r"it's C:\"), r"'it\'s C:\\'");
+    }
+
+    #[test]
+    fn names() {
+        assert!(is_name("_PATH2"));
+        assert!(!is_name(""));
+        assert!(!is_name("2X"));
+        assert!(!is_name("A;rm -rf ~"));
+    }
+}
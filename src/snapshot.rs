@@ -0,0 +1,248 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::Write as _;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::definition::{AppError, AppResult};
+use crate::env::{Env, RecordPair};
+use crate::source::{ReadOptions, Source};
+
+const FORMAT: &str = "printenv2-snapshot";
+const VERSION: u32 = 1;
+
+/// Variables that change between otherwise identical shells
+pub const VOLATILE: [&str; 4] = ["PWD", "OLDPWD", "SHLVL", "_"];
+
+/// Text when the bytes are valid UTF-8, hex-encoded otherwise, so records survive byte-exact
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Bytes {
+    Text(String),
+    Hex { hex: String },
+}
+
+impl From<&[u8]> for Bytes {
+    fn from(bytes: &[u8]) -> Self {
+        std::str::from_utf8(bytes).map_or_else(
+            |_| Self::Hex {
+                hex: bytes.iter().fold(String::new(), |mut hex, c| {
+                    write!(hex, "{c:02x}").unwrap();
+                    hex
+                }),
+            },
+            |text| Self::Text(text.to_owned()),
+        )
+    }
+}
+
+impl TryFrom<&Bytes> for Vec<u8> {
+    type Error = AppError;
+
+    fn try_from(bytes: &Bytes) -> AppResult<Self> {
+        match bytes {
+            Bytes::Text(text) => Ok(text.as_bytes().to_vec()),
+            Bytes::Hex { hex } => (0..hex.len())
+                .step_by(2)
+                .map(|i| {
+                    hex.get(i..i + 2)
+                        .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                        .ok_or_else(|| AppError::InvalidSnapshot(format!("invalid hex: {hex}")))
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Record {
+    key: Bytes,
+    value: Bytes,
+}
+
+/// A self-describing record of environment variables and where they came from
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    format: String,
+    version: u32,
+    source: String,
+    hostname: Option<String>,
+    pid: Option<u32>,
+    exe: Option<String>,
+    timestamp: String,
+    /// Variables left out when taking the snapshot, which are also ignored when comparing
+    excluded: Vec<String>,
+    records: Vec<Record>,
+}
+
+impl Snapshot {
+    pub fn new(source: &Source, options: &ReadOptions, env: &Env, excluded: &[&str]) -> Self {
+        #[cfg(not(procfs))]
+        let _ = options;
+        let (pid, exe) = match source {
+            Source::Current => (
+                Some(std::process::id()),
+                std::env::current_exe()
+                    .ok()
+                    .map(|exe| exe.display().to_string()),
+            ),
+            #[cfg(procfs)]
+            Source::Pid(pid) => (Some(*pid), exe_of(&options.procfs, *pid)),
+            #[cfg(all(remote_env, not(procfs)))]
+            Source::Pid(pid) => (Some(*pid), exe_of(*pid)),
+            #[cfg(procfs)]
+            Source::Process(process) => (Some(process.pid), exe_of(&options.procfs, process.pid)),
+            Source::File(_) | Source::Core(_) => (None, None),
+            Source::Via { pid, .. } => (*pid, None),
+            #[cfg(target_family = "unix")]
+            Source::Remote { .. } => (None, None),
+        };
+
+        Self {
+            format: FORMAT.to_owned(),
+            version: VERSION,
+            source: source.label(),
+            hostname: crate::platform_ext::hostname(),
+            pid,
+            exe,
+            timestamp: crate::timestamp::rfc3339(SystemTime::now()),
+            excluded: excluded.iter().map(|key| (*key).to_owned()).collect(),
+            records: env
+                .iter()
+                .filter(|record| !excluded.iter().any(|key| key.as_bytes() == record.0))
+                .map(|RecordPair(key, value)| Record {
+                    key: Bytes::from(&key[..]),
+                    value: Bytes::from(&value[..]),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn load(path: &Path) -> AppResult<Self> {
+        let snapshot: Self = serde_json::from_slice(&fs::read(path)?)?;
+        if snapshot.format != FORMAT {
+            return Err(AppError::InvalidSnapshot(format!(
+                "{} is not a printenv2 snapshot",
+                path.display()
+            )));
+        }
+        if snapshot.version > VERSION {
+            return Err(AppError::InvalidSnapshot(format!(
+                "{} has format version {}, newer than supported version {VERSION}",
+                path.display(),
+                snapshot.version
+            )));
+        }
+        Ok(snapshot)
+    }
+
+    /// Write the snapshot readable only by its owner, replacing `path` atomically
+    pub fn save(&self, path: &Path) -> AppResult<()> {
+        let mut content = serde_json::to_vec_pretty(self)?;
+        content.push(b'\n');
+
+        let file_name = path.file_name().ok_or_else(|| {
+            AppError::InvalidSnapshot(format!("{} is not a file path", path.display()))
+        })?;
+        let mut temp_name = std::ffi::OsString::from(".");
+        temp_name.push(file_name);
+        temp_name.push(format!(".{}.tmp", std::process::id()));
+        let temp_path = path.with_file_name(temp_name);
+
+        let result = Self::write_private(&temp_path, &content)
+            .and_then(|()| Ok(fs::rename(&temp_path, path)?));
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        result
+    }
+
+    fn write_private(path: &Path, content: &[u8]) -> AppResult<()> {
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(target_family = "unix")]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        let mut file: File = options.open(path)?;
+        file.write_all(content)?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    pub fn env(&self) -> AppResult<Env> {
+        Ok(Env(self
+            .records
+            .iter()
+            .map(|record| {
+                Ok(RecordPair(
+                    (&record.key).try_into()?,
+                    (&record.value).try_into()?,
+                ))
+            })
+            .collect::<AppResult<_>>()?))
+    }
+
+    pub fn excluded(&self) -> &[String] {
+        &self.excluded
+    }
+}
+
+#[cfg(procfs)]
+fn exe_of(procfs: &crate::procfs::Procfs, pid: u32) -> Option<String> {
+    procfs.exe(pid).ok().map(|exe| exe.display().to_string())
+}
+
+#[cfg(all(remote_env, not(procfs)))]
+fn exe_of(pid: u32) -> Option<String> {
+    std::fs::read_link(format!("/proc/{pid}/exe"))
+        .ok()
+        .map(|exe| exe.display().to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Snapshot, VOLATILE};
+    use crate::env::{Env, RecordPair};
+    use crate::source::{ReadOptions, Source};
+
+    #[test]
+    fn round_trip() {
+        let env = Env(vec![
+            RecordPair(b"TEXT".to_vec(), b"multi\nline".to_vec()),
+            RecordPair(b"BINARY".to_vec(), vec![0x54, 0xc3, 0x28]),
+            RecordPair(b"PWD".to_vec(), b"/tmp".to_vec()),
+        ]);
+        let snapshot = Snapshot::new(&Source::Current, &ReadOptions::default(), &env, &VOLATILE);
+        assert_eq!(snapshot.pid, Some(std::process::id()));
+        assert_eq!(snapshot.excluded(), VOLATILE);
+
+        let path = std::env::temp_dir().join(format!("printenv2-{}.snapshot", std::process::id()));
+        snapshot.save(&path).unwrap();
+
+        #[cfg(target_family = "unix")]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let loaded = Snapshot::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, snapshot);
+        let records: Vec<(Vec<u8>, Vec<u8>)> = loaded
+            .env()
+            .unwrap()
+            .0
+            .into_iter()
+            .map(|RecordPair(key, value)| (key, value))
+            .collect();
+        assert_eq!(
+            records,
+            vec![
+                (b"TEXT".to_vec(), b"multi\nline".to_vec()),
+                (b"BINARY".to_vec(), vec![0x54, 0xc3, 0x28]),
+            ]
+        );
+    }
+}
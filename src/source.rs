@@ -0,0 +1,276 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::str::FromStr;
+
+use crate::args::LoadFormat;
+use crate::definition::{AppError, AppResult};
+use crate::dotenv;
+use crate::env::Env;
+
+/// How sources are read, as given on the command line
+#[derive(Debug, Clone, Default)]
+pub struct ReadOptions {
+    /// Format of files
+    pub format: LoadFormat,
+    /// Whether dotenv values are expanded
+    pub expand: bool,
+    /// Where processes are read
+    #[cfg(procfs)]
+    pub procfs: crate::procfs::Procfs,
+    /// How `KEY_FILE` variables are resolved, with --resolve-file-refs
+    pub file_refs: Option<crate::file_refs::Resolver>,
+}
+
+/// Where a set of environment variables comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    Current,
+    #[cfg(remote_env)]
+    Pid(u32),
+    /// A process picked by a selector, read only if its PID has not been reused since
+    #[cfg(procfs)]
+    Process(crate::procfs::ProcessId),
+    /// A file in the format given by --load-format, or stdin if the path is `-`
+    File(PathBuf),
+    /// A core file of a crashed process
+    Core(PathBuf),
+    /// Output of a reader run behind a command prefix such as `ssh host`, for `pid` or the prefix's own environment
+    Via {
+        prefix: String,
+        pid: Option<u32>,
+    },
+    /// An agent started with `printenv2 serve`, asked for `target` which is `self`, `pid:<PID>` or `name:<NAME>`
+    #[cfg(target_family = "unix")]
+    Remote {
+        endpoint: crate::agent::Endpoint,
+        token: Option<String>,
+        target: String,
+    },
+}
+
+impl FromStr for Source {
+    type Err = String;
+
+    /// `self`, `pid:<PID>`, `core:<PATH>`, `via:<PREFIX>`, or a path to a file where `-` means stdin
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        if spec == "self" {
+            return Ok(Self::Current);
+        }
+        if let Some(prefix) = spec.strip_prefix("via:") {
+            return Ok(Self::Via {
+                prefix: prefix.to_owned(),
+                pid: None,
+            });
+        }
+        if let Some(pid) = spec.strip_prefix("pid:") {
+            #[cfg(remote_env)]
+            return pid
+                .parse()
+                .map(Self::Pid)
+                .map_err(|_| format!("invalid PID: {pid}"));
+            #[cfg(not(remote_env))]
+            return Err(format!(
+                "reading another process ({pid}) is not supported on this platform"
+            ));
+        }
+        if let Some(path) = spec.strip_prefix("core:") {
+            return Ok(Self::Core(PathBuf::from(path)));
+        }
+        Ok(Self::File(PathBuf::from(
+            spec.strip_prefix("file:").unwrap_or(spec),
+        )))
+    }
+}
+
+impl Source {
+    pub fn read(&self, options: &ReadOptions) -> AppResult<Env> {
+        let mut env = self
+            .read_raw(options)
+            .map_err(|err| err.context(self.describe()))?;
+        if let Some(resolver) = &options.file_refs {
+            resolver.resolve(&mut env, self.root(options).as_deref());
+        }
+        Ok(env)
+    }
+
+    /// Root directory of the process, where the files its variables name are, if it may not be ours
+    #[allow(clippy::unused_self, unused_variables)]
+    fn root(&self, options: &ReadOptions) -> Option<PathBuf> {
+        match self {
+            #[cfg(procfs)]
+            Self::Pid(pid) | Self::Process(crate::procfs::ProcessId { pid, .. }) => {
+                Some(options.procfs.path(*pid, "root"))
+            }
+            _ => None,
+        }
+    }
+
+    /// What reading the source does, for error messages
+    fn describe(&self) -> String {
+        match self {
+            Self::Current => "cannot read own environment".to_owned(),
+            #[cfg(remote_env)]
+            Self::Pid(pid) => format!(
+                "cannot read environment of process {pid} through {}",
+                crate::env::remote::BACKEND
+            ),
+            #[cfg(procfs)]
+            Self::Process(process) => format!(
+                "cannot read environment of process {} through {}",
+                process.pid,
+                crate::env::remote::BACKEND
+            ),
+            Self::File(path) if path == "-" => "cannot read stdin".to_owned(),
+            Self::File(path) => format!("cannot read {}", path.display()),
+            Self::Core(path) => format!("cannot read core file {}", path.display()),
+            Self::Via { .. } => format!("cannot read {}", self.label()),
+            #[cfg(target_family = "unix")]
+            Self::Remote {
+                endpoint, target, ..
+            } => format!("cannot ask the agent at {endpoint} for {target}"),
+        }
+    }
+
+    fn read_raw(&self, options: &ReadOptions) -> AppResult<Env> {
+        match self {
+            Self::Current => Ok(Env::new()),
+            #[cfg(procfs)]
+            Self::Pid(pid) => Ok(Env::from(
+                crate::remote_linux_procfs::get_environment_string(&options.procfs, *pid)?,
+            )),
+            #[cfg(all(remote_env, not(procfs)))]
+            Self::Pid(pid) => Ok(Env::from(crate::env::remote::get_environment_string(*pid)?)),
+            #[cfg(procfs)]
+            Self::Process(process) => Ok(Env::from(crate::remote_linux_procfs::read_environ(
+                &options.procfs,
+                *process,
+            )?)),
+            Self::File(path) => {
+                let mut content = Vec::new();
+                if path == "-" {
+                    let stdin = std::io::stdin();
+                    let mut reader = stdin.lock();
+                    reader.read_to_end(&mut content)?;
+                } else {
+                    let mut file = File::open(path)?;
+                    file.read_to_end(&mut content)?;
+                }
+                match (options.format, options.expand) {
+                    (LoadFormat::Null, _) => Ok(Env::from(content)),
+                    (LoadFormat::Dotenv, false) => dotenv::parse(std::str::from_utf8(&content)?),
+                    (LoadFormat::Dotenv, true) => dotenv::parse_expanded(
+                        std::str::from_utf8(&content)?,
+                        &Env::new(),
+                        &self.label(),
+                    ),
+                }
+            }
+            Self::Core(path) => Ok(Env::from(crate::coredump::read_environment(path)?)),
+            Self::Via { prefix, pid } => {
+                let reader = pid.map_or_else(
+                    || "env -0".to_owned(),
+                    |pid| format!("cat /proc/{pid}/environ"),
+                );
+                let command = format!("{prefix} {reader}");
+                let output = std::process::Command::new("sh")
+                    .arg("-c")
+                    .arg(&command)
+                    .stdin(Stdio::null())
+                    .stderr(Stdio::inherit())
+                    .output()?;
+                if !output.status.success() {
+                    return Err(AppError::Transport(format!(
+                        "`{command}` failed with {}",
+                        output.status
+                    )));
+                }
+                Ok(Env::from(output.stdout))
+            }
+            #[cfg(target_family = "unix")]
+            Self::Remote {
+                endpoint,
+                token,
+                target,
+            } => crate::agent::query(endpoint, token.as_deref(), target),
+        }
+    }
+
+    pub fn label(&self) -> String {
+        match self {
+            Self::Current => "self".to_owned(),
+            #[cfg(remote_env)]
+            Self::Pid(pid) => format!("pid:{pid}"),
+            #[cfg(procfs)]
+            Self::Process(process) => format!("pid:{}", process.pid),
+            Self::File(path) => path.display().to_string(),
+            Self::Core(path) => format!("core:{}", path.display()),
+            Self::Via { prefix, pid: None } => format!("self via {prefix}"),
+            Self::Via {
+                prefix,
+                pid: Some(pid),
+            } => format!("pid:{pid} via {prefix}"),
+            #[cfg(target_family = "unix")]
+            Self::Remote {
+                endpoint, target, ..
+            } => format!("{target} at {endpoint}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ReadOptions, Source};
+    use crate::definition::AppError;
+    use crate::env::RecordPair;
+    use std::path::PathBuf;
+
+    #[test]
+    fn parse() {
+        assert_eq!("self".parse(), Ok(Source::Current));
+        assert_eq!("./self".parse(), Ok(Source::File(PathBuf::from("./self"))));
+        assert_eq!("file:self".parse(), Ok(Source::File(PathBuf::from("self"))));
+        assert_eq!("-".parse(), Ok(Source::File(PathBuf::from("-"))));
+        assert_eq!("core:x".parse(), Ok(Source::Core(PathBuf::from("x"))));
+        assert_eq!(
+            "via:ssh web1".parse(),
+            Ok(Source::Via {
+                prefix: "ssh web1".to_owned(),
+                pid: None
+            })
+        );
+        #[cfg(remote_env)]
+        {
+            assert_eq!("pid:42".parse(), Ok(Source::Pid(42)));
+            assert!("pid:abc".parse::<Source>().is_err());
+        }
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn via() {
+        let source = Source::Via {
+            prefix: "env PRINTENV2_VIA=1".to_owned(),
+            pid: None,
+        };
+        assert!(
+            source
+                .read(&ReadOptions::default())
+                .unwrap()
+                .iter()
+                .any(|RecordPair(key, value)| key == b"PRINTENV2_VIA" && value == b"1")
+        );
+
+        let source = Source::Via {
+            prefix: "exit 3;".to_owned(),
+            pid: None,
+        };
+        let err = source.read(&ReadOptions::default()).unwrap_err();
+        assert!(matches!(err.root(), AppError::Transport(_)));
+        assert!(
+            err.to_string()
+                .starts_with("cannot read self via exit 3;: `exit 3; env -0` failed")
+        );
+    }
+}
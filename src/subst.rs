@@ -0,0 +1,329 @@
+use std::collections::HashMap;
+
+use crate::definition::{AppError, AppResult};
+use crate::env::Env;
+use crate::pattern::Pattern;
+
+/// Replace `$NAME`, `${NAME}`, `${NAME:-default}` and `${NAME:?message}` in `template` with variables of `env`.
+/// `$$` stands for a literal `$`, and references to names not matching `only` are left as they are.
+/// Variables which are not set and have no default fail with `strict`, and become empty otherwise
+pub fn substitute(
+    template: &[u8],
+    env: &Env,
+    only: &[Pattern],
+    strict: bool,
+) -> AppResult<Vec<u8>> {
+    Substitution::new(template, env, only, strict, "line ", 1).run()
+}
+
+/// Substitute a value found at `line` of the file `file`, which messages refer to as `file:line`.
+/// References to variables which are not set fail unless they have a default, and `defined` holds the line
+/// each variable of the file is first defined on, so that references to ones below fail too
+pub fn substitute_in(
+    value: &[u8],
+    env: &Env,
+    file: &str,
+    line: usize,
+    defined: &HashMap<Vec<u8>, usize>,
+) -> AppResult<Vec<u8>> {
+    let mut substitution = Substitution::new(value, env, &[], true, &format!("{file}:"), line);
+    substitution.defined = Some(defined);
+    substitution.run()
+}
+
+struct Substitution<'a> {
+    input: &'a [u8],
+    pos: usize,
+    line: usize,
+    env: &'a Env,
+    only: &'a [Pattern],
+    strict: bool,
+    /// What comes before line numbers in messages
+    location: String,
+    /// Variables which were not set, with the line they were referenced on
+    undefined: Vec<String>,
+    /// Lines variables of a dotenv file are first defined on
+    defined: Option<&'a HashMap<Vec<u8>, usize>>,
+}
+
+/// How a `${...}` expression treats a variable which is not set
+enum Operator {
+    None,
+    /// `-` or `:-`, and whether an empty value counts as not set
+    Default(bool),
+    /// `?` or `:?`, and whether an empty value counts as not set
+    Error(bool),
+}
+
+impl<'a> Substitution<'a> {
+    fn new(
+        input: &'a [u8],
+        env: &'a Env,
+        only: &'a [Pattern],
+        strict: bool,
+        location: &str,
+        line: usize,
+    ) -> Self {
+        Self {
+            input,
+            pos: 0,
+            line,
+            env,
+            only,
+            strict,
+            location: location.to_owned(),
+            undefined: Vec::new(),
+            defined: None,
+        }
+    }
+
+    fn run(mut self) -> AppResult<Vec<u8>> {
+        let output = self.expand(false, true)?;
+        if !self.undefined.is_empty() {
+            return Err(AppError::UndefinedVariable(self.undefined.join(", ")));
+        }
+        Ok(output)
+    }
+
+    /// Where `line` is, for messages
+    fn at(&self, line: usize) -> String {
+        format!("{}{line}", self.location)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    /// Text up to the end of the input, or up to the `}` closing an expression when `nested`.
+    /// References are only resolved when `evaluate`, so that unused defaults have no effect
+    fn expand(&mut self, nested: bool, evaluate: bool) -> AppResult<Vec<u8>> {
+        let mut output = Vec::new();
+        while let Some(c) = self.peek() {
+            match c {
+                b'}' if nested => return Ok(output),
+                b'$' => self.reference(&mut output, evaluate)?,
+                c => {
+                    if c == b'\n' {
+                        self.line += 1;
+                    }
+                    output.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+        Ok(output)
+    }
+
+    fn name(&mut self) -> &[u8] {
+        let start = self.pos;
+        if self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == b'_')
+        {
+            while self
+                .peek()
+                .is_some_and(|c| c.is_ascii_alphanumeric() || c == b'_')
+            {
+                self.pos += 1;
+            }
+        }
+        &self.input[start..self.pos]
+    }
+
+    fn is_substituted(&self, name: &[u8]) -> bool {
+        self.only.is_empty() || self.only.iter().any(|pattern| pattern.matches(name))
+    }
+
+    fn reference(&mut self, output: &mut Vec<u8>, evaluate: bool) -> AppResult<()> {
+        let start = self.pos;
+        let line = self.line;
+        self.pos += 1;
+        match self.peek() {
+            Some(b'$') => {
+                self.pos += 1;
+                output.push(b'$');
+                return Ok(());
+            }
+            Some(b'{') => self.pos += 1,
+            _ => {
+                let name = self.name().to_vec();
+                if name.is_empty() || !self.is_substituted(&name) {
+                    output.extend_from_slice(&self.input[start..self.pos]);
+                } else if evaluate {
+                    self.resolve(output, &name, line, &Operator::None, &[])?;
+                }
+                return Ok(());
+            }
+        }
+
+        let name = self.name().to_vec();
+        if name.is_empty() {
+            return Err(AppError::InvalidTemplate(format!(
+                "{}: expected a variable name after ${{",
+                self.at(line)
+            )));
+        }
+        let operator = match (self.peek(), self.input.get(self.pos + 1)) {
+            (Some(b'}'), _) => Operator::None,
+            (Some(b':'), Some(b'-')) => Operator::Default(true),
+            (Some(b':'), Some(b'?')) => Operator::Error(true),
+            (Some(b'-'), _) => Operator::Default(false),
+            (Some(b'?'), _) => Operator::Error(false),
+            _ => {
+                return Err(AppError::InvalidTemplate(format!(
+                    "{}: expected }}, :-, :?, - or ? after ${{{}",
+                    self.at(line),
+                    String::from_utf8_lossy(&name)
+                )));
+            }
+        };
+        self.pos += match operator {
+            Operator::None => 0,
+            Operator::Default(colon) | Operator::Error(colon) => 1 + usize::from(colon),
+        };
+
+        let substituted = evaluate && self.is_substituted(&name);
+        let word = self.expand(true, substituted && self.is_unset(&name, &operator))?;
+        if self.peek() != Some(b'}') {
+            return Err(AppError::InvalidTemplate(format!(
+                "{}: unterminated ${{{}",
+                self.at(line),
+                String::from_utf8_lossy(&name)
+            )));
+        }
+        self.pos += 1;
+
+        if substituted {
+            self.resolve(output, &name, line, &operator, &word)?;
+        } else if evaluate {
+            output.extend_from_slice(&self.input[start..self.pos]);
+        }
+        Ok(())
+    }
+
+    fn is_unset(&self, name: &[u8], operator: &Operator) -> bool {
+        match (self.env.get(name), operator) {
+            (None, _) => true,
+            (Some(value), Operator::Default(colon) | Operator::Error(colon)) => {
+                *colon && value.is_empty()
+            }
+            (Some(_), Operator::None) => false,
+        }
+    }
+
+    fn resolve(
+        &mut self,
+        output: &mut Vec<u8>,
+        name: &[u8],
+        line: usize,
+        operator: &Operator,
+        word: &[u8],
+    ) -> AppResult<()> {
+        let name_text = String::from_utf8_lossy(name);
+        if let Some(below) = self
+            .defined
+            .and_then(|defined| defined.get(name))
+            .filter(|below| **below > line)
+        {
+            return Err(AppError::InvalidDotenv(format!(
+                "{}: {name_text} is only defined below, on line {below}",
+                self.at(line)
+            )));
+        }
+        if !self.is_unset(name, operator) {
+            output.extend_from_slice(self.env.get(name).unwrap_or_default());
+            return Ok(());
+        }
+        match operator {
+            Operator::Default(_) => output.extend_from_slice(word),
+            Operator::Error(_) if word.is_empty() => {
+                return Err(AppError::UndefinedVariable(format!(
+                    "{}: {name_text} is not set",
+                    self.at(line)
+                )));
+            }
+            Operator::Error(_) => {
+                return Err(AppError::UndefinedVariable(format!(
+                    "{}: {name_text}: {}",
+                    self.at(line),
+                    String::from_utf8_lossy(word)
+                )));
+            }
+            Operator::None if self.strict => self
+                .undefined
+                .push(format!("{}: {name_text} is not set", self.at(line))),
+            Operator::None => eprintln!(
+                "printenv2: {}: {name_text} is not set, substituting an empty string",
+                self.at(line)
+            ),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::substitute;
+    use crate::definition::AppError;
+    use crate::env::Env;
+    use crate::pattern::Pattern;
+
+    fn run(template: &str, only: &[&str], strict: bool) -> Result<String, AppError> {
+        let env = Env::from(Vec::from("HOST=example.org\0PORT=8080\0EMPTY=\0"));
+        let only = only
+            .iter()
+            .map(|pattern| Pattern::new(pattern, false).unwrap())
+            .collect::<Vec<_>>();
+        substitute(template.as_bytes(), &env, &only, strict)
+            .map(|output| String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn references() {
+        assert_eq!(
+            run("listen $PORT;\nserver_name ${HOST};", &[], true).unwrap(),
+            "listen 8080;\nserver_name example.org;"
+        );
+        assert_eq!(
+            run("$$HOST costs $5 $", &[], true).unwrap(),
+            "$HOST costs $5 $"
+        );
+        assert_eq!(
+            run("${MISSING:-${HOST}:$PORT}", &[], true).unwrap(),
+            "example.org:8080"
+        );
+        assert_eq!(run("${EMPTY:-x} ${EMPTY-x}", &[], true).unwrap(), "x ");
+        assert_eq!(run("${HOST:-$MISSING}", &[], true).unwrap(), "example.org");
+        assert_eq!(run("$MISSING.", &[], false).unwrap(), ".");
+    }
+
+    #[test]
+    fn only() {
+        assert_eq!(
+            run("$HOST $uri ${PORT} ${uri-x}", &["HOST", "P*"], true).unwrap(),
+            "example.org $uri 8080 ${uri-x}"
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let err = run("a\n$MISSING ${OTHER}", &[], true).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 2: MISSING is not set, line 2: OTHER is not set"
+        );
+        assert_eq!(
+            run("${EMPTY:?must be set}", &[], false)
+                .unwrap_err()
+                .to_string(),
+            "line 1: EMPTY: must be set"
+        );
+        assert!(run("${EMPTY?}", &[], true).is_ok());
+        for template in ["${HOST", "${}", "${HOST/x/y}", "${HOST:-x"] {
+            assert!(
+                matches!(run(template, &[], true), Err(AppError::InvalidTemplate(_))),
+                "{template}"
+            );
+        }
+    }
+}
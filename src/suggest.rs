@@ -0,0 +1,53 @@
+/// The name among `candidates` closest to `name`, if any is close enough to be a likely typo.
+/// Case is ignored, and swapping two adjacent characters counts as a single edit.
+pub fn closest<'a>(name: &str, candidates: impl IntoIterator<Item = &'a [u8]>) -> Option<&'a [u8]> {
+    let name = name.to_ascii_uppercase();
+    let limit = (name.len() / 4).max(1);
+    candidates
+        .into_iter()
+        .map(|candidate| {
+            (
+                distance(name.as_bytes(), &candidate.to_ascii_uppercase()),
+                candidate,
+            )
+        })
+        .filter(|(distance, _)| *distance <= limit)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Optimal string alignment distance
+fn distance(a: &[u8], b: &[u8]) -> usize {
+    let mut rows = vec![(0..=b.len()).collect::<Vec<_>>()];
+    for i in 1..=a.len() {
+        let mut row = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            row[j] = (rows[i - 1][j] + 1)
+                .min(row[j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(rows[i - 2][j - 2] + 1);
+            }
+        }
+        rows.push(row);
+    }
+    rows[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod test {
+    use super::{closest, distance};
+
+    #[test]
+    fn typos() {
+        assert_eq!(distance(b"HOEM", b"HOME"), 1);
+        assert_eq!(distance(b"EDITR", b"EDITOR"), 1);
+        assert_eq!(distance(b"", b"ABC"), 3);
+
+        let names: [&[u8]; 4] = [b"HOME", b"PATH", b"EDITOR", b"Path"];
+        assert_eq!(closest("HOEM", names), Some(&b"HOME"[..]));
+        assert_eq!(closest("path", names), Some(&b"PATH"[..]));
+        assert_eq!(closest("SHELL", names), None);
+    }
+}
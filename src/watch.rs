@@ -0,0 +1,203 @@
+use serde::Serialize;
+use std::io::Write;
+use std::time::{Duration, SystemTime};
+
+use crate::definition::{AppError, AppResult, ErrorKind};
+use crate::diff::EnvDiff;
+use crate::env::Env;
+use crate::printer::Printer;
+use crate::source::{ReadOptions, Source};
+use crate::timestamp::rfc3339;
+
+/// Parse durations such as `2s`, `500ms` or `1m`. A bare number means seconds
+pub fn parse_interval(interval: &str) -> Result<Duration, String> {
+    let split = interval
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(interval.len());
+    let (number, unit) = interval.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid interval: {interval}"))?;
+    let seconds = match unit {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        _ => return Err(format!("invalid unit of interval: {unit}")),
+    };
+    Duration::try_from_secs_f64(seconds)
+        .ok()
+        .filter(|duration| !duration.is_zero())
+        .ok_or_else(|| format!("invalid interval: {interval}"))
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+enum Event<'a> {
+    Change {
+        time: String,
+        source: &'a str,
+        changes: &'a EnvDiff,
+    },
+    Exit {
+        time: String,
+        source: &'a str,
+    },
+}
+
+/// Waits for the next poll, and tells whether the watched process has exited meanwhile
+struct Waiter {
+    #[cfg(procfs)]
+    pidfd: Option<crate::pidfd::PidFd>,
+    #[cfg(procfs)]
+    pid: Option<u32>,
+    #[cfg(procfs)]
+    procfs: crate::procfs::Procfs,
+}
+
+impl Waiter {
+    #[allow(clippy::missing_const_for_fn)]
+    fn new(source: &Source, options: &ReadOptions) -> Self {
+        #[cfg(procfs)]
+        {
+            let pid = match source {
+                Source::Pid(pid) | Source::Process(crate::procfs::ProcessId { pid, .. }) => {
+                    Some(*pid)
+                }
+                _ => None,
+            };
+            let pidfd = pid
+                .filter(|_| options.procfs.is_native())
+                .and_then(|pid| crate::pidfd::PidFd::open(pid).ok());
+            Self {
+                pidfd,
+                pid,
+                procfs: options.procfs.clone(),
+            }
+        }
+        #[cfg(not(procfs))]
+        {
+            let _ = (source, options);
+            Self {}
+        }
+    }
+
+    fn wait(&self, interval: Duration) -> AppResult<bool> {
+        #[cfg(procfs)]
+        {
+            if let Some(pidfd) = &self.pidfd {
+                return pidfd.wait_exit(interval);
+            }
+            std::thread::sleep(interval);
+            // Without a pidfd, the process is gone once its procfs directory is
+            Ok(self
+                .pid
+                .is_some_and(|pid| !self.procfs.path(pid, "stat").exists()))
+        }
+        #[cfg(not(procfs))]
+        {
+            std::thread::sleep(interval);
+            Ok(false)
+        }
+    }
+
+    /// Whether `err` from reading the source means the watched process has exited, rather than that reading
+    /// failed this time, such as with EACCES or EIO
+    fn has_exited(&self, err: &AppError) -> AppResult<bool> {
+        Ok(err.kind() == ErrorKind::NotFound || self.wait(Duration::ZERO)?)
+    }
+}
+
+/// Print changes of environment variables as they happen, until the watched process exits
+pub fn watch(
+    source: &Source,
+    options: &ReadOptions,
+    interval: Duration,
+    variables: &[String],
+    printer: &Printer,
+) -> AppResult<()> {
+    let read = || -> AppResult<Env> {
+        let mut env = source.read(options)?;
+        if !variables.is_empty() {
+            env.filter_keys(variables);
+        }
+        Ok(env)
+    };
+
+    let label = source.label();
+    let waiter = Waiter::new(source, options);
+    let mut previous = read()?;
+    loop {
+        let current = if waiter.wait(interval)? {
+            None
+        } else {
+            match read() {
+                Ok(env) => Some(env),
+                Err(err) if waiter.has_exited(&err)? => None,
+                Err(err) => {
+                    eprintln!("printenv2: {err}, trying again");
+                    continue;
+                }
+            }
+        };
+
+        let Some(current) = current else {
+            let event = Event::Exit {
+                time: rfc3339(SystemTime::now()),
+                source: &label,
+            };
+            print_event(&event, printer)?;
+            return Ok(());
+        };
+
+        let diff = EnvDiff::new(&previous, &current);
+        if !diff.is_empty() {
+            let event = Event::Change {
+                time: rfc3339(SystemTime::now()),
+                source: &label,
+                changes: &diff,
+            };
+            print_event(&event, printer)?;
+        }
+        previous = current;
+    }
+}
+
+fn print_event(event: &Event, printer: &Printer) -> AppResult<()> {
+    let mut stdout = std::io::stdout().lock();
+    if printer.json {
+        serde_json::to_writer(&mut stdout, event)?;
+        writeln!(stdout)?;
+    } else {
+        match event {
+            Event::Change { time, changes, .. } => {
+                for line in printer
+                    .print_diff(changes)?
+                    .split_inclusive(|c| *c == b'\n')
+                {
+                    write!(stdout, "{time} ")?;
+                    stdout.write_all(line)?;
+                }
+            }
+            Event::Exit { time, source } => writeln!(stdout, "{time} {source} exited")?,
+        }
+    }
+    stdout.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse_interval;
+    use std::time::Duration;
+
+    #[test]
+    fn interval() {
+        assert_eq!(parse_interval("2s"), Ok(Duration::from_secs(2)));
+        assert_eq!(parse_interval("2"), Ok(Duration::from_secs(2)));
+        assert_eq!(parse_interval("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_interval("1.5m"), Ok(Duration::from_secs(90)));
+        assert!(parse_interval("0s").is_err());
+        assert!(parse_interval("2h").is_err());
+        assert!(parse_interval("s").is_err());
+    }
+}
@@ -0,0 +1,32 @@
+//! Diffing environments read through command prefixes, given as `via:<PREFIX>` sides.
+#![cfg(unix)]
+
+use std::process::Command;
+
+#[test]
+fn via_sides() {
+    let output = Command::new(env!("CARGO_BIN_EXE_printenv2"))
+        .args([
+            "diff",
+            "via:env -i A=1 B=2",
+            r#"via:sh -c 'exec env -i A=1 B=3 C=4 "$@"' sh"#,
+            "--format",
+            "json",
+        ])
+        .output()
+        .unwrap();
+    assert_eq!(
+        output.status.code(),
+        Some(1),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap(),
+        serde_json::json!({
+            "added": {"C": "4"},
+            "removed": {},
+            "changed": {"B": {"old": "2", "new": "3"}}
+        })
+    );
+}
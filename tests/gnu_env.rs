@@ -0,0 +1,110 @@
+//! Conformance with GNU coreutils env, for installing printenv2 in its place.
+//! Each case runs printenv2 through a symlink named `env`, and GNU env when it is in PATH.
+#![cfg(unix)]
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+const ENVIRONMENT: &[(&str, &str)] = &[
+    ("HOME", "/home/user"),
+    ("TABS", "a\tb"),
+    ("MULTILINE", "first\nsecond"),
+];
+
+const CASES: &[&[&str]] = &[
+    &[],
+    &["-i", "A=x\ty", "B=l1\nl2"],
+    &["-u", "HOME"],
+    &["-", "C=1"],
+    &["-0", "-i", "A=x\ny"],
+    &["-i", "A=1", "A=2"],
+    &["-iu", "B", "C=2"],
+    &["-uHOME"],
+    &["-0i", "A=1"],
+    &["-u", "HOME", "-uTABS", "-C", "/", "pwd"],
+];
+
+/// `env` as a symlink to the built binary, in a directory of its own
+fn install() -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("gnu_env");
+    std::fs::create_dir_all(&dir).unwrap();
+    let link = dir.join("env");
+    // Tests run in parallel, so another one may have created it already
+    if let Err(err) = std::os::unix::fs::symlink(env!("CARGO_BIN_EXE_printenv2"), &link) {
+        assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+    }
+    link
+}
+
+fn gnu_env() -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join("env"))
+        .find(|path| path.is_file())
+        .filter(|path| {
+            Command::new(path)
+                .arg("--version")
+                .output()
+                .is_ok_and(|output| output.stdout.starts_with(b"env (GNU coreutils)"))
+        })
+}
+
+fn run(program: &Path, args: &[&str]) -> Output {
+    Command::new(program)
+        .args(args)
+        .env_clear()
+        .envs(ENVIRONMENT.iter().copied())
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn expected_output() {
+    let env = install();
+    let expectations: &[(&[&str], &[u8])] = &[
+        (&["-i", "A=x\ty", "B=l1\nl2"], b"A=x\ty\nB=l1\nl2\n"),
+        (&["-u", "HOME"], b"MULTILINE=first\nsecond\nTABS=a\tb\n"),
+        (&["-0", "-i", "A=x\ny"], b"A=x\ny\0"),
+        (&["-iu", "B", "C=2"], b"C=2\n"),
+        (&["-0iuHOME", "A=1"], b"A=1\0"),
+        (&["-iC/", "pwd"], b"/\n"),
+    ];
+    for (args, stdout) in expectations {
+        let output = run(&env, args);
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(stdout),
+            "env {args:?}"
+        );
+        assert_eq!(output.status.code(), Some(0), "env {args:?}");
+    }
+}
+
+#[test]
+fn same_as_gnu() {
+    let Some(gnu) = gnu_env() else {
+        eprintln!("GNU env is not in PATH, skipping");
+        return;
+    };
+    let env = install();
+    for args in CASES {
+        let expected = run(&gnu, args);
+        let actual = run(&env, args);
+        assert_eq!(
+            String::from_utf8_lossy(&actual.stdout),
+            String::from_utf8_lossy(&expected.stdout),
+            "env {args:?}"
+        );
+        assert_eq!(actual.status.code(), expected.status.code(), "env {args:?}");
+    }
+}
+
+#[test]
+fn exec_null() {
+    let output = run(
+        Path::new(env!("CARGO_BIN_EXE_printenv2")),
+        &["exec", "-0", "-i", "A=x\ny", "B=2"],
+    );
+    assert_eq!(output.stdout, b"A=x\ny\0B=2\0");
+    assert_eq!(output.status.code(), Some(0));
+}
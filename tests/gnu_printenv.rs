@@ -0,0 +1,137 @@
+//! Conformance with GNU coreutils printenv, for installing printenv2 in its place.
+//! Each case runs printenv2 through a symlink named `printenv`, and GNU printenv when it is in PATH.
+#![cfg(unix)]
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+const ENVIRONMENT: &[(&str, &str)] = &[
+    ("HOME", "/home/user"),
+    ("EMPTY", ""),
+    ("MULTILINE", "first\nsecond"),
+    ("TABS", "a\tb"),
+    ("PATH", "/usr/bin:/bin"),
+    ("diff", "1"),
+    ("exec", "run"),
+];
+
+const CASES: &[&[&str]] = &[
+    &[],
+    &["HOME"],
+    &["PATH", "HOME"],
+    &["HOME", "PATH", "HOME"],
+    &["EMPTY"],
+    &["MULTILINE", "TABS"],
+    &["MISSING"],
+    &["HOME", "MISSING", "PATH"],
+    &["HOME=/home/user"],
+    &["-0", "HOME", "PATH"],
+    &["-0"],
+    &["--", "HOME"],
+    &["diff"],
+    &["exec", "HOME"],
+    &["history"],
+    &["-0", "export", "diff"],
+    &["HOME", "hook", "subst", "capture", "serve"],
+];
+
+/// A directory holding `printenv` as a symlink to the built binary
+fn install() -> PathBuf {
+    let link = Path::new(env!("CARGO_TARGET_TMPDIR")).join("printenv");
+    // Tests run in parallel, so another one may have created it already
+    if let Err(err) = std::os::unix::fs::symlink(env!("CARGO_BIN_EXE_printenv2"), &link) {
+        assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+    }
+    link
+}
+
+fn gnu_printenv() -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join("printenv"))
+        .find(|path| path.is_file())
+        .filter(|path| {
+            Command::new(path)
+                .arg("--version")
+                .output()
+                .is_ok_and(|output| output.stdout.starts_with(b"printenv (GNU coreutils)"))
+        })
+}
+
+fn run(program: &Path, args: &[&str]) -> Output {
+    Command::new(program)
+        .args(args)
+        .env_clear()
+        .envs(ENVIRONMENT.iter().copied())
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn expected_output() {
+    let printenv = install();
+    let expectations: &[(&[&str], &[u8], i32)] = &[
+        (
+            &["HOME", "PATH", "HOME"],
+            b"/home/user\n/usr/bin:/bin\n/home/user\n",
+            0,
+        ),
+        (&["EMPTY"], b"\n", 0),
+        (&["MULTILINE", "TABS"], b"first\nsecond\na\tb\n", 0),
+        (
+            &["HOME", "MISSING", "PATH"],
+            b"/home/user\n/usr/bin:/bin\n",
+            1,
+        ),
+        (&["MISSING"], b"", 1),
+        (&["HOME=/home/user"], b"", 1),
+        (&["-0", "HOME", "PATH"], b"/home/user\0/usr/bin:/bin\0", 0),
+        (&["diff"], b"1\n", 0),
+        (&["exec", "HOME"], b"run\n/home/user\n", 0),
+        (&["history"], b"", 1),
+        (&["-0", "export", "diff"], b"1\0", 1),
+    ];
+    for (args, stdout, code) in expectations {
+        let output = run(&printenv, args);
+        assert_eq!(output.stdout, *stdout, "printenv {args:?}");
+        assert_eq!(output.status.code(), Some(*code), "printenv {args:?}");
+    }
+}
+
+#[test]
+fn same_as_gnu() {
+    let Some(gnu) = gnu_printenv() else {
+        eprintln!("GNU printenv is not in PATH, skipping");
+        return;
+    };
+    let printenv = install();
+    for args in CASES {
+        let expected = run(&gnu, args);
+        let actual = run(&printenv, args);
+        assert_eq!(
+            String::from_utf8_lossy(&actual.stdout),
+            String::from_utf8_lossy(&expected.stdout),
+            "printenv {args:?}"
+        );
+        assert_eq!(
+            actual.status.code(),
+            expected.status.code(),
+            "printenv {args:?}"
+        );
+    }
+}
+
+#[test]
+fn report_missing() {
+    let output = Command::new(env!("CARGO_BIN_EXE_printenv2"))
+        .args(["--report-missing", "HOEM", "SHELL"])
+        .env_clear()
+        .envs(ENVIRONMENT.iter().copied())
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "printenv2: HOEM is not set; did you mean HOME?\nprintenv2: SHELL is not set\n"
+    );
+}
@@ -0,0 +1,57 @@
+//! JSON output with and without VARIABLES, for a single environment and for process groups.
+
+use std::process::Command;
+
+fn printenv2() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_printenv2"))
+}
+
+#[test]
+fn whole_environment() {
+    let output = printenv2()
+        .arg("--json")
+        .env_clear()
+        .envs([("A", "1"), ("B", "2")])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap(),
+        serde_json::json!({"A": "1", "B": "2"})
+    );
+
+    let output = printenv2()
+        .args(["--json", "B", "B"])
+        .env_clear()
+        .envs([("A", "1"), ("B", "2")])
+        .output()
+        .unwrap();
+    assert_eq!(
+        serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap(),
+        serde_json::json!({"B": "2"})
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn all_processes() {
+    let mut child = Command::new("sleep")
+        .arg("1000")
+        .env_clear()
+        .env("MARKER", "json-all")
+        .spawn()
+        .unwrap();
+    let output = printenv2().args(["--all", "--json"]).output().unwrap();
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    let processes: Vec<serde_json::Value> = serde_json::from_slice(&output.stdout).unwrap();
+    let process = processes
+        .iter()
+        .find(|process| process["pid"] == child.id())
+        .expect("the child is listed");
+    assert_eq!(
+        process["environment"],
+        serde_json::json!({"MARKER": "json-all"})
+    );
+}